@@ -0,0 +1,197 @@
+//! This module backfills the historical events in parallel.
+//! The blocks `[start, end]` are split into contiguous shards, which are fetched by concurrent
+//! workers. Each shard has its own cursor and checkpoint, an interrupted backfill resumes every
+//! shard from its checkpoint. The ranges of the shards are merged before the callbacks: in block
+//! order by default, or in the order they are fetched for more throughput. In block order, the
+//! workers of the later shards keep fetching, their ranges wait in memory for the earlier shards.
+use crate::{
+    event_stream::{self, CancelHandle},
+    shutdown::DEFAULT_DRAIN_TIMEOUT,
+    quarantine::{DeadLetterStore, MemoryDeadLetterStore, DEFAULT_QUARANTINE_AFTER},
+    tracker::{self, Cursor, EventOrigin},
+    CheckpointStore, Erc1155EventCallback, Erc721EventCallback, EventsConfig, EvmClient, Result, RetryPolicy,
+    RateLimit, RpcTimeouts, TrackerConfig,
+};
+use std::{sync::Arc, time::Duration};
+
+/// The default number of shards fetched at the same time
+pub const DEFAULT_SHARDS: usize = 4;
+
+/// How the historical blocks are backfilled
+#[derive(Debug, Clone)]
+pub struct BackfillConfig {
+    /// The first block to backfill
+    pub start: u64,
+    /// The last block to backfill
+    pub end: u64,
+    /// The number of blocks of a range
+    pub step: u64,
+    /// The number of shards, fetched by as many concurrent workers
+    pub shards: usize,
+    /// Pass the events to the callbacks in block order, up to `spool` ranges of each later shard are
+    /// kept in memory until the earlier shards are done.
+    /// If `false`, the ranges are passed as soon as they are fetched, whatever their shard.
+    pub ordered: bool,
+    /// The maximum number of ranges fetched ahead by each worker
+    pub prefetch: usize,
+    /// The maximum number of ranges kept in memory by each later shard until the earlier shards are done
+    pub spool: usize,
+    /// Stops the backfill once the current range is done, shared by the clones of the config
+    pub shutdown: CancelHandle,
+    /// The time given to the current range to be done after a shutdown
+    pub drain_timeout: Duration,
+    /// When the failed requests are sent again
+    pub retry_policy: RetryPolicy,
+    /// The number of failed attempts of a range before its bad logs are quarantined
+    pub quarantine_after: Option<u32>,
+    /// Where the quarantined logs are kept
+    pub dead_letters: Arc<dyn DeadLetterStore>,
+    /// The time given to an endpoint to answer, by method
+    pub rpc_timeouts: RpcTimeouts,
+    /// The quota of the rpc endpoint
+    pub rate_limit: RateLimit,
+    /// The time given to fetch the events of a range before it is fetched again
+    pub range_deadline: Option<Duration>,
+}
+
+impl BackfillConfig {
+    /// Backfill `[start, end]` by ranges of `step` blocks, with the default number of shards, in block order
+    pub fn new(start: u64, end: u64, step: u64) -> BackfillConfig {
+        BackfillConfig {
+            start,
+            end,
+            step,
+            shards: DEFAULT_SHARDS,
+            ordered: true,
+            prefetch: tracker::DEFAULT_PREFETCH,
+            spool: tracker::DEFAULT_SPOOL,
+            shutdown: CancelHandle::new(),
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            retry_policy: RetryPolicy::default(),
+            quarantine_after: Some(DEFAULT_QUARANTINE_AFTER),
+            dead_letters: Arc::new(MemoryDeadLetterStore::default()),
+            rpc_timeouts: RpcTimeouts::default(),
+            rate_limit: RateLimit::default(),
+            range_deadline: Some(tracker::DEFAULT_RANGE_DEADLINE),
+        }
+    }
+
+    /// Backfill `[tracker_config.start_from, end]` with the step, the spool, the retries, the quarantine,
+    /// the timeouts, the rate limit and the shutdown of `tracker_config`, with the default number of shards
+    pub fn from_tracker_config(tracker_config: &TrackerConfig, end: u64) -> BackfillConfig {
+        BackfillConfig {
+            prefetch: tracker_config.prefetch,
+            spool: tracker_config.spool,
+            shutdown: tracker_config.shutdown.clone(),
+            drain_timeout: tracker_config.drain_timeout,
+            retry_policy: tracker_config.retry_policy.clone(),
+            quarantine_after: tracker_config.quarantine_after,
+            dead_letters: tracker_config.dead_letters.clone(),
+            rpc_timeouts: tracker_config.rpc_timeouts.clone(),
+            rate_limit: tracker_config.rate_limit.clone(),
+            range_deadline: tracker_config.range_deadline,
+            ..BackfillConfig::new(tracker_config.start_from, end, tracker_config.step)
+        }
+    }
+
+    /// The name of the cursor of a shard.
+    /// The bounds and the number of shards are part of the name, the checkpoints of another split are not reused.
+    pub fn cursor(&self, shard: usize) -> String {
+        format!("backfill-{}-{}-{}-{}", self.start, self.end, self.shards, shard)
+    }
+}
+
+/// Split `[start, end]` into at most `shards` contiguous shards of the same size, the last one may be shorter
+pub fn split_shards(start: u64, end: u64, shards: usize) -> Vec<(u64, u64)> {
+    if start > end {
+        return vec![];
+    }
+
+    let blocks = end - start + 1;
+    let size = (blocks + shards.max(1) as u64 - 1) / shards.max(1) as u64;
+    let mut result = vec![];
+    let mut from = start;
+    while from <= end {
+        let to = std::cmp::min(from + size - 1, end);
+        result.push((from, to));
+        if to == end {
+            break;
+        }
+        from = to + 1;
+    }
+    result
+}
+
+/// Backfill the events of `[start, end]` with concurrent workers, see `BackfillConfig`.
+/// Returns when all the shards are done, once the current range is done after a shutdown, or with
+/// the error which halted the backfill.
+pub async fn backfill_events(
+    evm_client: &EvmClient,
+    config: &EventsConfig,
+    backfill_config: &BackfillConfig,
+    checkpoints: &dyn CheckpointStore,
+    erc721_cb: &mut dyn Erc721EventCallback,
+    erc1155_cb: &mut dyn Erc1155EventCallback,
+) -> Result<()> {
+    let cursors: Vec<Cursor> = split_shards(backfill_config.start, backfill_config.end, backfill_config.shards)
+        .into_iter()
+        .enumerate()
+        .map(|(shard, (from, to))| {
+            let name = backfill_config.cursor(shard);
+            Ok(Cursor {
+                from: tracker::resume_from(checkpoints, &name, from)?,
+                name,
+                end: Some(to),
+                origin: EventOrigin::Historical,
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    let mut tracker_config = TrackerConfig::new(backfill_config.start, backfill_config.step);
+    tracker_config.prefetch = backfill_config.prefetch;
+    tracker_config.spool = backfill_config.spool;
+    tracker_config.shutdown = backfill_config.shutdown.clone();
+    tracker_config.retry_policy = backfill_config.retry_policy.clone();
+    tracker_config.quarantine_after = backfill_config.quarantine_after;
+    tracker_config.dead_letters = backfill_config.dead_letters.clone();
+    tracker_config.rpc_timeouts = backfill_config.rpc_timeouts.clone();
+    tracker_config.range_deadline = backfill_config.range_deadline;
+
+    // The shards are contiguous, their ranges in shard order are in block order
+    let cursors = async move { Ok((cursors, backfill_config.ordered)) };
+    let (events, tracking) = event_stream::tracked_events(evm_client, config, &tracker_config, cursors, checkpoints);
+    tracker::run_callbacks(events, tracking, erc721_cb, erc1155_cb).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_shards() {
+        assert_eq!(vec![(0, 24), (25, 49), (50, 74), (75, 99)], split_shards(0, 99, 4));
+        assert_eq!(vec![(10, 13), (14, 17), (18, 20)], split_shards(10, 20, 3));
+        // no more shards than blocks
+        assert_eq!(vec![(5, 5), (6, 6)], split_shards(5, 6, 4));
+        assert_eq!(vec![(5, 6)], split_shards(5, 6, 0));
+        assert!(split_shards(7, 6, 4).is_empty());
+    }
+
+    #[test]
+    fn test_from_tracker_config() {
+        let mut tracker_config = TrackerConfig::new(100, 6);
+        tracker_config.spool = 3;
+        tracker_config.quarantine_after = None;
+        tracker_config.range_deadline = Some(Duration::from_secs(5));
+        let backfill_config = BackfillConfig::from_tracker_config(&tracker_config, 200);
+        assert_eq!((100, 200, 6), (backfill_config.start, backfill_config.end, backfill_config.step));
+        assert_eq!(DEFAULT_SHARDS, backfill_config.shards);
+        assert_eq!(3, backfill_config.spool);
+        assert_eq!(None, backfill_config.quarantine_after);
+        assert_eq!(Some(Duration::from_secs(5)), backfill_config.range_deadline);
+
+        // the shutdown is shared
+        tracker_config.shutdown.cancel();
+        assert!(backfill_config.shutdown.is_cancelled());
+    }
+}
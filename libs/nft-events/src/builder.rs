@@ -0,0 +1,516 @@
+//! This module contains the builder of the tracker.
+//! The `TrackerBuilder` collects the options of a tracking and validates them, it builds a `Tracker`
+//! which is run, observed and shut down through a shared reference, e.g. from other tasks.
+use crate::{
+    shutdown,
+    sink::{EventBatch, EventSink},
+    tracker, CheckpointStore, DeadLetterStore, Error, EventsConfig, EvmClient, FailoverTransport, MemoryCheckpointStore, Result,
+    RateLimit, RetryPolicy, RpcTimeouts, Standard, TrackerConfig, Watchlist,
+};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use web3::types::H160;
+
+/// Builds a `Tracker`
+pub struct TrackerBuilder {
+    chain_name: String,
+    endpoints: Vec<String>,
+    rate_limits: Vec<RateLimit>,
+    start_from: Option<u64>,
+    tracker_config: TrackerConfig,
+    events_config: EventsConfig,
+    checkpoints: Option<Arc<dyn CheckpointStore>>,
+    sinks: Vec<Box<dyn EventSink>>,
+}
+
+impl TrackerBuilder {
+    /// A tracker of `chain_name`, with the default options
+    pub fn new(chain_name: &str) -> TrackerBuilder {
+        TrackerBuilder {
+            chain_name: chain_name.to_owned(),
+            endpoints: vec![],
+            rate_limits: vec![],
+            start_from: None,
+            tracker_config: TrackerConfig::new(0, 0),
+            events_config: EventsConfig::default(),
+            checkpoints: None,
+            sinks: vec![],
+        }
+    }
+
+    /// Add an RPC endpoint, the next endpoints are used when the previous ones cannot be reached
+    pub fn rpc(mut self, url: &str) -> Self {
+        self.endpoints.push(url.to_owned());
+        self.rate_limits.push(RateLimit::default());
+        self
+    }
+
+    /// Add an RPC endpoint whose requests are limited by `rate_limit`, see `rpc`
+    pub fn rpc_with_rate_limit(mut self, url: &str, rate_limit: RateLimit) -> Self {
+        self.endpoints.push(url.to_owned());
+        self.rate_limits.push(rate_limit);
+        self
+    }
+
+    /// The first block to scan if there is no checkpoint, required
+    pub fn start_block(mut self, start_from: u64) -> Self {
+        self.start_from = Some(start_from);
+        self
+    }
+
+    /// The last block to scan, the tracking follows the head if it is not set
+    pub fn end_block(mut self, end_block: u64) -> Self {
+        self.tracker_config.end_block = Some(end_block);
+        self
+    }
+
+    /// The number of blocks of a range, required
+    pub fn step(mut self, step: u64) -> Self {
+        self.tracker_config.step = step;
+        self
+    }
+
+    /// The number of blocks behind the head which are not scanned yet
+    pub fn confirmations(mut self, confirmations: u64) -> Self {
+        self.tracker_config.confirmations = confirmations;
+        self
+    }
+
+    /// The time to wait for new blocks once the tracking has caught up with the head
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.tracker_config.poll_interval = poll_interval;
+        self
+    }
+
+    /// The name of the cursor, its checkpoint is saved under this name
+    pub fn cursor(mut self, cursor: &str) -> Self {
+        self.tracker_config.cursor = cursor.to_owned();
+        self
+    }
+
+    /// Follow the head from now while the history is backfilled from the start block
+    pub fn concurrent_backfill(mut self, concurrent_backfill: bool) -> Self {
+        self.tracker_config.concurrent_backfill = concurrent_backfill;
+        self
+    }
+
+    /// The time given to the current range to be done after a shutdown
+    pub fn drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.tracker_config.drain_timeout = drain_timeout;
+        self
+    }
+
+    /// The number of times a batch is delivered to the sinks before the tracking halts
+    pub fn sink_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.tracker_config.sink_max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// When the failed requests of the head, the logs and the metadata are sent again
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.tracker_config.retry_policy = retry_policy;
+        self
+    }
+
+    /// Only the events of these contracts are tracked
+    pub fn allowlist<I: IntoIterator<Item = H160>>(mut self, addresses: I) -> Self {
+        self.events_config.allowlist = addresses.into_iter().collect();
+        self
+    }
+
+    /// The events of these contracts are never tracked
+    pub fn denylist<I: IntoIterator<Item = H160>>(mut self, addresses: I) -> Self {
+        self.events_config.denylist = addresses.into_iter().collect();
+        self
+    }
+
+    /// Only the transfers from or to the watched addresses are tracked
+    pub fn watchlist(mut self, watchlist: Watchlist) -> Self {
+        self.events_config.watchlist = Some(watchlist);
+        self
+    }
+
+    /// Only the events of these standards are tracked, all by default
+    pub fn standards<I: IntoIterator<Item = Standard>>(mut self, standards: I) -> Self {
+        self.events_config.standards = standards.into_iter().collect();
+        self
+    }
+
+    /// Replace the whole events config, the options set before on it are lost
+    pub fn events_config(mut self, events_config: EventsConfig) -> Self {
+        self.events_config = events_config;
+        self
+    }
+
+    /// Where the checkpoints are saved, in memory by default
+    pub fn checkpoints<C: CheckpointStore + 'static>(mut self, checkpoints: C) -> Self {
+        self.checkpoints = Some(Arc::new(checkpoints));
+        self
+    }
+
+    /// The number of failed attempts of a range before its bad logs are quarantined
+    pub fn quarantine_after(mut self, attempts: u32) -> Self {
+        self.tracker_config.quarantine_after = Some(attempts);
+        self
+    }
+
+    /// The time given to an endpoint to answer, by method
+    pub fn rpc_timeouts(mut self, rpc_timeouts: RpcTimeouts) -> Self {
+        self.tracker_config.rpc_timeouts = rpc_timeouts;
+        self
+    }
+
+    /// The time given to fetch the events of a range before it is fetched again
+    pub fn range_deadline(mut self, range_deadline: Duration) -> Self {
+        self.tracker_config.range_deadline = Some(range_deadline);
+        self
+    }
+
+    /// Where the quarantined logs are kept, in memory by default
+    pub fn dead_letters<D: DeadLetterStore + 'static>(mut self, dead_letters: D) -> Self {
+        self.tracker_config.dead_letters = Arc::new(dead_letters);
+        self
+    }
+
+    /// Add a sink, the batches are delivered to the sinks in the order they were added.
+    /// At least one is required.
+    pub fn sink<S: EventSink + 'static>(mut self, sink: S) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Validate the options and build the tracker
+    pub fn build(self) -> Result<Tracker> {
+        let invalid = |reason: &str| Err(Error::ConfigError(format!("Invalid tracker config: {}", reason)));
+        if self.endpoints.is_empty() {
+            return invalid("no rpc endpoint");
+        }
+        let start_from = match self.start_from {
+            Some(start_from) => start_from,
+            None => return invalid("no start block"),
+        };
+        if self.tracker_config.step == 0 {
+            return invalid("the step must be at least 1 block");
+        }
+        if let Some(end_block) = self.tracker_config.end_block {
+            if end_block < start_from {
+                return invalid("the end block is before the start block");
+            }
+        }
+        if self.tracker_config.poll_interval == Duration::from_secs(0) {
+            return invalid("the poll interval must not be zero");
+        }
+        if self.tracker_config.sink_max_attempts == Some(0) {
+            return invalid("a batch must be delivered at least once");
+        }
+        let retry_policy = &self.tracker_config.retry_policy;
+        if retry_policy.base_delay > retry_policy.max_delay {
+            return invalid("the base retry delay is longer than the maximum retry delay");
+        }
+        if !(0.0..=1.0).contains(&retry_policy.jitter) {
+            return invalid("the retry jitter must be between 0 and 1");
+        }
+        if retry_policy.max_attempts == Some(0) {
+            return invalid("a request must be sent at least once");
+        }
+        if self.tracker_config.quarantine_after == Some(0) {
+            return invalid("a range must be tried at least once before it is quarantined");
+        }
+        let rpc_timeouts = &self.tracker_config.rpc_timeouts;
+        if [rpc_timeouts.logs, rpc_timeouts.head, rpc_timeouts.call].contains(&Duration::from_secs(0)) {
+            return invalid("the rpc timeouts must not be zero");
+        }
+        if self.tracker_config.range_deadline == Some(Duration::from_secs(0)) {
+            return invalid("the range deadline must not be zero");
+        }
+        for rate_limit in &self.rate_limits {
+            if matches!(rate_limit.requests_per_second, Some(rps) if !(rps > 0.0 && rps.is_finite())) {
+                return invalid("the requests per second of an endpoint must be positive");
+            }
+            if rate_limit.burst == Some(0) || rate_limit.max_concurrent == Some(0) {
+                return invalid("the burst and the concurrent requests of an endpoint must not be zero");
+            }
+        }
+        if self.tracker_config.cursor.is_empty() {
+            return invalid("the cursor has no name");
+        }
+        if self.events_config.standards.is_empty() {
+            return invalid("no standard to track");
+        }
+        if self.sinks.is_empty() {
+            return invalid("no sink");
+        }
+
+        let transport = FailoverTransport::from_urls(&self.endpoints)?
+            .with_timeouts(self.tracker_config.rpc_timeouts.clone())
+            .with_rate_limits(&self.rate_limits);
+        let mut tracker_config = self.tracker_config;
+        tracker_config.start_from = start_from;
+        let status = Arc::new(Mutex::new(TrackerStatus::default()));
+        Ok(Tracker {
+            client: EvmClient::with_transport(self.chain_name, transport),
+            tracker_config,
+            events_config: self.events_config,
+            checkpoints: self.checkpoints.unwrap_or_else(|| Arc::new(MemoryCheckpointStore::default())),
+            sinks: tokio::sync::Mutex::new(Sinks {
+                sinks: self.sinks,
+                acked: vec![],
+                status: status.clone(),
+            }),
+            status,
+        })
+    }
+}
+
+/// The state of a tracker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackerState {
+    /// Not run yet
+    Idle,
+    /// Tracking
+    Running,
+    /// Done, all the blocks up to the end block were tracked
+    Finished,
+    /// Shut down before the end
+    Stopped,
+    /// Halted by an error
+    Failed(String),
+}
+
+impl Default for TrackerState {
+    fn default() -> Self {
+        TrackerState::Idle
+    }
+}
+
+/// What a tracker has done so far
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackerStatus {
+    /// The state of the tracker
+    pub state: TrackerState,
+    /// The last block acknowledged by the sinks, for each cursor
+    pub cursors: BTreeMap<String, u64>,
+    /// The number of events acknowledged by the sinks
+    pub events: u64,
+}
+
+/// Delivers the batches to all the sinks and records the progress
+struct Sinks {
+    sinks: Vec<Box<dyn EventSink>>,
+    /// The `(cursor, to)` of the batch being delivered, for each sink which acknowledged it
+    acked: Vec<Option<(String, u64)>>,
+    status: Arc<Mutex<TrackerStatus>>,
+}
+
+#[async_trait]
+impl EventSink for Sinks {
+    async fn on_batch(&mut self, batch: &EventBatch) -> Result<()> {
+        // A batch which failed on a sink is delivered again, only to the sinks which did not acknowledge it
+        self.acked.resize(self.sinks.len(), None);
+        let range = Some((batch.cursor.clone(), batch.to));
+        for (sink, acked) in self.sinks.iter_mut().zip(self.acked.iter_mut()) {
+            if *acked != range {
+                sink.on_batch(batch).await?;
+                *acked = range.clone();
+            }
+        }
+        self.acked.iter_mut().for_each(|acked| *acked = None);
+
+        let mut status = self.status.lock().unwrap();
+        status.cursors.insert(batch.cursor.clone(), batch.to);
+        status.events += batch.events.len() as u64;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        for sink in self.sinks.iter_mut() {
+            sink.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+/// A tracker built by the `TrackerBuilder`
+pub struct Tracker {
+    client: EvmClient,
+    tracker_config: TrackerConfig,
+    events_config: EventsConfig,
+    checkpoints: Arc<dyn CheckpointStore>,
+    sinks: tokio::sync::Mutex<Sinks>,
+    status: Arc<Mutex<TrackerStatus>>,
+}
+
+impl Tracker {
+    /// Track the events until the end block if any, a shutdown or an error of the sinks.
+    /// A tracker runs once at a time, it can be run again after it has returned, unless it was shut down.
+    pub async fn run(&self) -> Result<()> {
+        let mut sinks = self
+            .sinks
+            .try_lock()
+            .map_err(|_| Error::AlreadyRunning)?;
+        self.status.lock().unwrap().state = TrackerState::Running;
+
+        let shutdown = &self.tracker_config.shutdown;
+        let tracking = tracker::track_events_to_sink(
+            &self.client,
+            &self.events_config,
+            &self.tracker_config,
+            self.checkpoints.as_ref(),
+            &mut *sinks,
+        );
+        let result = shutdown::drain(tracking, shutdown, self.tracker_config.drain_timeout).await;
+        let result = match result {
+            Err(err) if shutdown.is_cancelled() => {
+                // the range was aborted, flush what the sinks have received before it
+                error!("{}", err);
+                let flushed = sinks.flush().await.and(self.checkpoints.flush());
+                flushed.and(Err(err))
+            },
+            result => result,
+        };
+
+        self.status.lock().unwrap().state = match &result {
+            Err(err) => TrackerState::Failed(err.to_string()),
+            Ok(()) if shutdown.is_cancelled() => TrackerState::Stopped,
+            Ok(()) => TrackerState::Finished,
+        };
+        result
+    }
+
+    /// Stop the tracking once the current range is done, `run` returns after the sinks and
+    /// the checkpoints are flushed, or at the latest after the drain timeout.
+    /// The ranges which were not acknowledged by all the sinks are tracked again on the next run.
+    pub fn shutdown(&self) {
+        self.tracker_config.shutdown.cancel();
+    }
+
+    /// Shut down the tracker on `SIGINT` or `SIGTERM`
+    pub fn shutdown_on_signals(&self) {
+        shutdown::shutdown_on_signals(&self.tracker_config.shutdown);
+    }
+
+    /// What the tracker has done so far
+    pub fn status(&self) -> TrackerStatus {
+        self.status.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoopSink {}
+
+    #[async_trait]
+    impl EventSink for NoopSink {
+        async fn on_batch(&mut self, _batch: &EventBatch) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Fails its first `failures` batches, records the batches it acknowledged
+    struct RecordingSink {
+        failures: u32,
+        acked: Arc<Mutex<Vec<u64>>>,
+    }
+
+    #[async_trait]
+    impl EventSink for RecordingSink {
+        async fn on_batch(&mut self, batch: &EventBatch) -> Result<()> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(Error::Other("sink down".to_owned()));
+            }
+            self.acked.lock().unwrap().push(batch.to);
+            Ok(())
+        }
+    }
+
+    fn builder() -> TrackerBuilder {
+        TrackerBuilder::new("Ethereum")
+            .rpc("http://127.0.0.1:1")
+            .start_block(100)
+            .step(10)
+            .sink(NoopSink {})
+    }
+
+    #[test]
+    fn test_build_tracker() {
+        let tracker = builder().end_block(200).confirmations(12).build().unwrap();
+        assert_eq!(100, tracker.tracker_config.start_from);
+        assert_eq!(12, tracker.tracker_config.confirmations);
+        assert_eq!(TrackerState::Idle, tracker.status().state);
+
+        let tracker = builder().rpc_with_rate_limit("http://127.0.0.1:2", RateLimit::new(10.0)).build().unwrap();
+        let transport = tracker.client.transport();
+        assert_eq!(None, transport.limiter(0).unwrap().limit().requests_per_second);
+        assert_eq!(Some(10.0), transport.limiter(1).unwrap().limit().requests_per_second);
+    }
+
+    #[test]
+    fn test_validate_tracker() {
+        assert!(TrackerBuilder::new("Ethereum").start_block(100).step(10).sink(NoopSink {}).build().is_err());
+        assert!(TrackerBuilder::new("Ethereum").rpc("http://127.0.0.1:1").step(10).sink(NoopSink {}).build().is_err());
+        assert!(builder().rpc("not a url").build().is_err());
+        assert!(matches!(builder().step(0).build(), Err(Error::ConfigError(_))));
+        assert!(builder().end_block(99).build().is_err());
+        assert!(builder().poll_interval(Duration::from_secs(0)).build().is_err());
+        assert!(builder().standards(vec![]).build().is_err());
+        let retry_policy = RetryPolicy::new(Duration::from_secs(10), Duration::from_secs(1));
+        assert!(builder().retry_policy(retry_policy).build().is_err());
+        let retry_policy = RetryPolicy { jitter: 2.0, ..RetryPolicy::default() };
+        assert!(builder().retry_policy(retry_policy).build().is_err());
+        assert!(builder().quarantine_after(0).build().is_err());
+        let rpc_timeouts = RpcTimeouts { head: Duration::from_secs(0), ..RpcTimeouts::default() };
+        assert!(builder().rpc_timeouts(rpc_timeouts).build().is_err());
+        assert!(builder().range_deadline(Duration::from_secs(0)).build().is_err());
+        assert!(builder().rpc_with_rate_limit("http://127.0.0.1:2", RateLimit::new(0.0)).build().is_err());
+        let rate_limit = RateLimit { max_concurrent: Some(0), ..RateLimit::new(10.0) };
+        assert!(builder().rpc_with_rate_limit("http://127.0.0.1:2", rate_limit).build().is_err());
+        assert!(TrackerBuilder::new("Ethereum").rpc("http://127.0.0.1:1").start_block(100).step(10).build().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_redeliver_to_failed_sinks() {
+        let healthy = Arc::new(Mutex::new(vec![]));
+        let flaky = Arc::new(Mutex::new(vec![]));
+        let status = Arc::new(Mutex::new(TrackerStatus::default()));
+        let mut sinks = Sinks {
+            sinks: vec![
+                Box::new(RecordingSink { failures: 0, acked: healthy.clone() }),
+                Box::new(RecordingSink { failures: 2, acked: flaky.clone() }),
+            ],
+            acked: vec![],
+            status: status.clone(),
+        };
+
+        for &to in &[9, 19] {
+            let batch = EventBatch { cursor: "main".to_owned(), from: to - 9, to, events: vec![] };
+            crate::sink::deliver(&mut sinks, &batch, None, Duration::from_millis(1)).await.unwrap();
+        }
+        // the healthy sink got each batch once, though the first one was delivered three times
+        assert_eq!(vec![9, 19], *healthy.lock().unwrap());
+        assert_eq!(vec![9, 19], *flaky.lock().unwrap());
+        assert_eq!(Some(&19), status.lock().unwrap().cursors.get("main"));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_tracker() {
+        let tracker = Arc::new(builder().build().unwrap());
+        let running = tracker.clone();
+        let run = tokio::spawn(async move { running.run().await });
+
+        // nothing answers on the endpoint, the tracker keeps running until it is shut down
+        while tracker.status().state != TrackerState::Running {
+            tokio::task::yield_now().await;
+        }
+        assert!(matches!(tracker.run().await, Err(Error::AlreadyRunning)));
+
+        tracker.shutdown();
+        run.await.unwrap().unwrap();
+        assert_eq!(TrackerState::Stopped, tracker.status().state);
+    }
+}
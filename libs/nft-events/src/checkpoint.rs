@@ -0,0 +1,116 @@
+//! This module contains the checkpoints of the tracker.
+//! A checkpoint is the last block whose events were all passed to the callbacks, the tracking
+//! resumes from the next block. Each cursor of the tracker has its own checkpoint, saved under
+//! the name of the cursor.
+use crate::{Error, Result};
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+/// Where the checkpoints are saved
+pub trait CheckpointStore: Send + Sync {
+    /// The checkpoint of a cursor, `None` if the cursor has never saved one
+    fn load(&self, cursor: &str) -> Result<Option<u64>>;
+
+    /// Save the checkpoint of a cursor
+    fn save(&self, cursor: &str, block_number: u64) -> Result<()>;
+
+    /// Write what the store still buffers, called when the tracking stops.
+    /// Does nothing by default, the checkpoints are saved by `save`.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The checkpoints are kept in memory and lost on exit, shared by all the clones of the store
+#[derive(Debug, Clone, Default)]
+pub struct MemoryCheckpointStore {
+    checkpoints: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl CheckpointStore for MemoryCheckpointStore {
+    fn load(&self, cursor: &str) -> Result<Option<u64>> {
+        Ok(self.checkpoints.lock().unwrap().get(cursor).cloned())
+    }
+
+    fn save(&self, cursor: &str, block_number: u64) -> Result<()> {
+        self.checkpoints.lock().unwrap().insert(cursor.to_owned(), block_number);
+        Ok(())
+    }
+}
+
+/// The checkpoint of each cursor is saved in the file `<cursor>.checkpoint` of a directory.
+/// The file is replaced atomically, a crash never leaves a partially written checkpoint.
+#[derive(Debug, Clone)]
+pub struct FileCheckpointStore {
+    dir: PathBuf,
+}
+
+impl FileCheckpointStore {
+    /// Save the checkpoints in `dir`, which is created if it does not exist
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Result<FileCheckpointStore> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|e| Error::Other(format!("Failed to create {:?}: {}", dir, e)))?;
+        Ok(FileCheckpointStore { dir })
+    }
+
+    fn path(&self, cursor: &str) -> PathBuf {
+        self.dir.join(format!("{}.checkpoint", cursor))
+    }
+}
+
+impl CheckpointStore for FileCheckpointStore {
+    fn load(&self, cursor: &str) -> Result<Option<u64>> {
+        let path = self.path(cursor);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&path).map_err(|e| Error::Other(format!("Failed to read {:?}: {}", path, e)))?;
+        let block_number = content
+            .trim()
+            .parse::<u64>()
+            .map_err(|e| Error::Other(format!("Invalid checkpoint in {:?}: {}", path, e)))?;
+        Ok(Some(block_number))
+    }
+
+    fn save(&self, cursor: &str, block_number: u64) -> Result<()> {
+        let path = self.path(cursor);
+        let tmp_path = path.with_extension("checkpoint.tmp");
+        fs::write(&tmp_path, block_number.to_string())
+            .and_then(|_| fs::rename(&tmp_path, &path))
+            .map_err(|e| Error::Other(format!("Failed to write {:?}: {}", path, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_checkpoint_store() {
+        let store = MemoryCheckpointStore::default();
+        assert_eq!(None, store.load("live").unwrap());
+
+        store.save("live", 100).unwrap();
+        store.save("backfill", 10).unwrap();
+        assert_eq!(Some(100), store.clone().load("live").unwrap());
+        assert_eq!(Some(10), store.load("backfill").unwrap());
+    }
+
+    #[test]
+    fn test_file_checkpoint_store() {
+        let dir = std::env::temp_dir().join(format!("nft-events-checkpoints-{}", std::process::id()));
+        let store = FileCheckpointStore::new(&dir).unwrap();
+        assert_eq!(None, store.load("live").unwrap());
+
+        store.save("live", 100).unwrap();
+        store.save("live", 106).unwrap();
+        assert_eq!(Some(106), FileCheckpointStore::new(&dir).unwrap().load("live").unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,426 @@
+//! This module classifies the decoded events.
+//! The decoding of the logs is pure, the classification decides which events are kept: only the
+//! events of visual NFT contracts are tracked. The classification is pluggable, the `EvmClient`
+//! asks the node and caches the answers, the `ContractCache` answers from what is already known.
+use crate::{
+    ContractCache, ContractFeature, ContractFingerprint, Error, Event, EventsConfig,
+    EvmClient, Result, TransferKind,
+};
+use futures::{stream, StreamExt};
+use std::collections::BTreeSet;
+use web3::types::{H160, U256};
+
+/// Decides which contracts are tracked
+#[async_trait]
+pub trait Classifier: Send + Sync {
+    /// Is an ERC721 contract which supports the metadata extension
+    async fn is_visual_erc721(&self, address: H160) -> Result<bool>;
+
+    /// Is an ERC1155 contract which supports the metadata extension
+    async fn is_visual_erc1155(&self, address: H160) -> Result<bool>;
+
+    /// Supports ERC5192
+    async fn is_erc5192(&self, address: H160) -> Result<bool>;
+
+    /// Is the token locked by its ERC5192 contract at `block`, at the latest block if it is `None`
+    async fn is_locked(&self, address: H160, token_id: &U256, block: Option<u64>) -> Result<bool>;
+}
+
+/// Classify the events decoded by `decode_log`.
+/// The events of the contracts which are not visual NFTs are dropped, the ERC721 transfers are
+/// marked as soulbound and the transfers to the burn addresses of `config` are marked as burns.
+pub async fn classify_events(classifier: &dyn Classifier, config: &EventsConfig, events: Vec<Event>) -> Result<Vec<Event>> {
+    let mut result = vec![];
+    for event in events {
+        match event {
+            Event::Erc721(mut e) => {
+                if classifier.is_visual_erc721(e.address).await? {
+                    e.kind = TransferKind::classify(&e.from, &e.to, &config.burn_addresses);
+                    e.soulbound = is_soulbound(classifier, e.address, &e.token_id, e.block_number).await?;
+                    result.push(Event::Erc721(e));
+                }
+            },
+            Event::Erc1155(mut e) => {
+                if classifier.is_visual_erc1155(e.address).await? {
+                    e.kind = TransferKind::classify(&e.from, &e.to, &config.burn_addresses);
+                    result.push(Event::Erc1155(e));
+                }
+            },
+            Event::Erc5192(e) => {
+                if classifier.is_visual_erc721(e.address).await? && classifier.is_erc5192(e.address).await? {
+                    result.push(Event::Erc5192(e));
+                }
+            },
+        }
+    }
+    Ok(result)
+}
+
+/// Classify the contracts of `events` at the same time, up to `concurrency` contracts at once.
+/// Each contract is classified once, so a caching classifier like the `EvmClient` answers the
+/// following `classify_events` from its cache. The errors are ignored here, they are reported
+/// again by `classify_events` with the log which caused them.
+pub async fn preclassify_contracts<'a, I>(classifier: &dyn Classifier, events: I, concurrency: usize)
+where
+    I: IntoIterator<Item = &'a Event>,
+{
+    // (address, is an ERC1155 contract)
+    let mut contracts = BTreeSet::new();
+    for event in events {
+        match event {
+            Event::Erc721(e) => contracts.insert((e.address, false)),
+            Event::Erc1155(e) => contracts.insert((e.address, true)),
+            Event::Erc5192(e) => contracts.insert((e.address, false)),
+        };
+    }
+
+    stream::iter(contracts)
+        .map(|(address, erc1155)| async move {
+            if erc1155 {
+                let _ = classifier.is_visual_erc1155(address).await;
+            } else if let Ok(true) = classifier.is_visual_erc721(address).await {
+                let _ = classifier.is_erc5192(address).await;
+            }
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect::<Vec<()>>()
+        .await;
+}
+
+/// A token is soulbound if its contract implements ERC5192 and reports it as locked at `block`.
+/// `locked` reverts for tokens that do not exist (anymore), they are treated as not soulbound.
+/// A node which does not keep the state of `block`, e.g. it is not an archive node, is asked at the
+/// latest block instead. The other errors are returned, the event is not tracked with a wrong flag.
+async fn is_soulbound(classifier: &dyn Classifier, address: H160, token_id: &U256, block: Option<u64>) -> Result<bool> {
+    if !classifier.is_erc5192(address).await? {
+        return Ok(false);
+    }
+    let locked = match classifier.is_locked(address, token_id, block).await {
+        Err(err @ Error::RpcError { .. }) if block.is_some() => {
+            warn!("The lock of {} of {:?} is read at the latest block: {}", token_id, address, err);
+            classifier.is_locked(address, token_id, None).await
+        },
+        locked => locked,
+    };
+    match locked {
+        Err(Error::RevertedCall { .. }) => Ok(false),
+        locked => locked,
+    }
+}
+
+/// Asks the node, the answers are cached in the contract cache of the client
+#[async_trait]
+impl Classifier for EvmClient {
+    async fn is_visual_erc721(&self, address: H160) -> Result<bool> {
+        if let Some(visual) = self.contract_cache().get(&address).and_then(|info| info.visual_erc721) {
+            return Ok(visual);
+        }
+
+        let supported = match self.is_erc721(address).await {
+            Ok(true) => self.supports_erc721_metadata(address).await,
+            supported => supported,
+        };
+        let visual = match supported {
+            Ok(true) => true,
+            // ERC165 is not implemented or its answer is wrong, look for the ERC721 functions in the bytecode
+            Ok(false) | Err(Error::RevertedCall { .. }) => {
+                let fingerprint = get_fingerprint(self, address).await?;
+                fingerprint.has(ContractFeature::Erc721) && fingerprint.has(ContractFeature::Erc721Metadata)
+            },
+            Err(err) => return Err(err),
+        };
+        self.contract_cache().update(address, |info| info.visual_erc721 = Some(visual));
+        Ok(visual)
+    }
+
+    async fn is_visual_erc1155(&self, address: H160) -> Result<bool> {
+        if let Some(visual) = self.contract_cache().get(&address).and_then(|info| info.visual_erc1155) {
+            return Ok(visual);
+        }
+
+        let supported = match self.is_erc1155(address).await {
+            Ok(true) => self.supports_erc1155_metadata(address).await,
+            supported => supported,
+        };
+        let visual = match supported {
+            Ok(true) => true,
+            // ERC165 is not implemented or its answer is wrong, look for the ERC1155 functions in the bytecode
+            Ok(false) | Err(Error::RevertedCall { .. }) => {
+                let fingerprint = get_fingerprint(self, address).await?;
+                fingerprint.has(ContractFeature::Erc1155) && fingerprint.has(ContractFeature::Erc1155MetadataUri)
+            },
+            Err(err) => return Err(err),
+        };
+        self.contract_cache().update(address, |info| info.visual_erc1155 = Some(visual));
+        Ok(visual)
+    }
+
+    async fn is_erc5192(&self, address: H160) -> Result<bool> {
+        if let Some(supported) = self.contract_cache().get(&address).and_then(|info| info.erc5192) {
+            return Ok(supported);
+        }
+
+        let supported = self.supports_erc5192(address).await?;
+        self.contract_cache().update(address, |info| info.erc5192 = Some(supported));
+        Ok(supported)
+    }
+
+    async fn is_locked(&self, address: H160, token_id: &U256, block: Option<u64>) -> Result<bool> {
+        self.get_erc5192_locked(&address, token_id, block).await
+    }
+}
+
+async fn get_fingerprint(client: &EvmClient, address: H160) -> Result<ContractFingerprint> {
+    if let Some(fingerprint) = client.contract_cache().get(&address).and_then(|info| info.fingerprint) {
+        return Ok(fingerprint);
+    }
+
+    let fingerprint = client.fingerprint_contract(&address).await?;
+    client.contract_cache().update(address, |info| info.fingerprint = Some(fingerprint.clone()));
+    Ok(fingerprint)
+}
+
+/// Answers from the cached classification only, without a node.
+/// The unknown contracts are not tracked and no token is known to be locked.
+#[async_trait]
+impl Classifier for ContractCache {
+    async fn is_visual_erc721(&self, address: H160) -> Result<bool> {
+        Ok(self.get(&address).and_then(|info| info.visual_erc721).unwrap_or(false))
+    }
+
+    async fn is_visual_erc1155(&self, address: H160) -> Result<bool> {
+        Ok(self.get(&address).and_then(|info| info.visual_erc1155).unwrap_or(false))
+    }
+
+    async fn is_erc5192(&self, address: H160) -> Result<bool> {
+        Ok(self.get(&address).and_then(|info| info.erc5192).unwrap_or(false))
+    }
+
+    async fn is_locked(&self, _address: H160, _token_id: &U256, _block: Option<u64>) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Erc1155Event, Erc721Event};
+    use std::str::FromStr;
+
+    fn erc721_event(address: H160, to: H160) -> Event {
+        Event::Erc721(Erc721Event {
+            block_number: Some(100),
+            block_hash: None,
+            block_timestamp: None,
+            address,
+            transaction_hash: None,
+            transaction_index: None,
+            log_index: Some(0),
+            removed: false,
+            from: H160::from_low_u64_be(10),
+            to,
+            token_id: U256::from(1),
+            kind: TransferKind::Transfer,
+            soulbound: false,
+        })
+    }
+
+    fn erc1155_event(address: H160) -> Event {
+        Event::Erc1155(Erc1155Event {
+            block_number: Some(100),
+            block_hash: None,
+            block_timestamp: None,
+            address,
+            transaction_hash: None,
+            transaction_index: None,
+            log_index: Some(1),
+            removed: false,
+            operator: H160::from_low_u64_be(10),
+            from: H160::from_low_u64_be(10),
+            to: H160::from_low_u64_be(11),
+            token_id: U256::from(1),
+            amount: U256::from(1),
+            kind: TransferKind::Transfer,
+            batch_index: 0,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_classify_with_contract_cache() {
+        let visual = H160::from_low_u64_be(1);
+        let unknown = H160::from_low_u64_be(2);
+        let cache = ContractCache::default();
+        cache.update(visual, |info| info.visual_erc721 = Some(true));
+
+        let dead = H160::from_str(crate::events_helper::DEAD_ADDRESS).unwrap();
+        let events = vec![
+            erc721_event(visual, dead),
+            erc721_event(unknown, dead),
+            erc1155_event(visual),
+        ];
+        let events = classify_events(&cache, &EventsConfig::default(), events).await.unwrap();
+
+        assert_eq!(1, events.len());
+        match &events[0] {
+            Event::Erc721(e) => {
+                assert_eq!(visual, e.address);
+                assert_eq!(TransferKind::Burn, e.kind);
+                assert_eq!(false, e.soulbound);
+            },
+            _ => panic!("not an erc721 event"),
+        }
+    }
+
+    /// Counts the classification calls
+    #[derive(Default)]
+    struct CountingClassifier {
+        calls: std::sync::Mutex<Vec<H160>>,
+    }
+
+    #[async_trait]
+    impl Classifier for CountingClassifier {
+        async fn is_visual_erc721(&self, address: H160) -> Result<bool> {
+            self.calls.lock().unwrap().push(address);
+            Ok(true)
+        }
+
+        async fn is_visual_erc1155(&self, address: H160) -> Result<bool> {
+            self.calls.lock().unwrap().push(address);
+            Ok(true)
+        }
+
+        async fn is_erc5192(&self, _address: H160) -> Result<bool> {
+            Ok(false)
+        }
+
+        async fn is_locked(&self, _address: H160, _token_id: &U256, _block: Option<u64>) -> Result<bool> {
+            Ok(false)
+        }
+    }
+
+    /// An ERC5192 classifier whose `locked` at a block answers with `locked`
+    struct LockingClassifier {
+        locked: fn(Option<u64>) -> Result<bool>,
+        blocks: std::sync::Mutex<Vec<Option<u64>>>,
+    }
+
+    #[async_trait]
+    impl Classifier for LockingClassifier {
+        async fn is_visual_erc721(&self, _address: H160) -> Result<bool> {
+            Ok(true)
+        }
+
+        async fn is_visual_erc1155(&self, _address: H160) -> Result<bool> {
+            Ok(true)
+        }
+
+        async fn is_erc5192(&self, _address: H160) -> Result<bool> {
+            Ok(true)
+        }
+
+        async fn is_locked(&self, _address: H160, _token_id: &U256, block: Option<u64>) -> Result<bool> {
+            self.blocks.lock().unwrap().push(block);
+            (self.locked)(block)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_classify_soulbound() {
+        let address = H160::from_low_u64_be(1);
+        let classify = |locked: fn(Option<u64>) -> Result<bool>| async move {
+            let classifier = LockingClassifier {
+                locked,
+                blocks: Default::default(),
+            };
+            let events = vec![erc721_event(address, H160::from_low_u64_be(11))];
+            let result = classify_events(&classifier, &EventsConfig::default(), events).await;
+            // read at the block of the event
+            assert_eq!(vec![Some(100)], *classifier.blocks.lock().unwrap());
+            result.map(|events| matches!(&events[0], Event::Erc721(e) if e.soulbound))
+        };
+
+        assert_eq!(true, classify(|_| Ok(true)).await.unwrap());
+        // the token does not exist anymore
+        let reverted = |_| {
+            Err(Error::RevertedCall {
+                address: H160::from_low_u64_be(1),
+                method: "locked".to_owned(),
+                message: "execution reverted".to_owned(),
+            })
+        };
+        assert_eq!(false, classify(reverted).await.unwrap());
+        // the node failed, the flag is unknown
+        let unreachable = |_| Err(Error::rpc(web3::Error::Unreachable, "locked"));
+        assert!(matches!(classify(unreachable).await, Err(Error::TransientRpcError { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_soulbound_without_archive_node() {
+        // the node does not keep the state of the old blocks
+        let classifier = LockingClassifier {
+            locked: |block| match block {
+                Some(_) => {
+                    let err = jsonrpc_core::Error {
+                        code: jsonrpc_core::ErrorCode::ServerError(-32000),
+                        message: "missing trie node".to_owned(),
+                        data: None,
+                    };
+                    Err(Error::rpc(web3::Error::Rpc(err), "locked"))
+                },
+                None => Ok(true),
+            },
+            blocks: Default::default(),
+        };
+        let events = vec![erc721_event(H160::from_low_u64_be(1), H160::from_low_u64_be(11))];
+        let events = classify_events(&classifier, &EventsConfig::default(), events).await.unwrap();
+        assert!(matches!(&events[0], Event::Erc721(e) if e.soulbound));
+        assert_eq!(vec![Some(100), None], *classifier.blocks.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_classify_with_wrong_erc165() {
+        use crate::rpc_stub;
+        use web3::{signing::keccak256, transports::Http, Web3};
+
+        // supportsInterface is false for everything, the bytecode has the ERC721 functions
+        let mut code = "0x".to_owned();
+        for function in &["ownerOf(uint256)", "balanceOf(address)", "safeTransferFrom(address,address,uint256)", "tokenURI(uint256)"] {
+            code.push_str(&format!("63{}", hex(&keccak256(function.as_bytes())[..4])));
+        }
+        let url = rpc_stub::serve(move |method, _params| match method {
+            "eth_call" => Ok(rpc_stub::encode_bool(false)),
+            "eth_getCode" => Ok(serde_json::json!(code)),
+            "eth_getStorageAt" => Ok(serde_json::json!(format!("0x{:064x}", 0))),
+            _ => Err(jsonrpc_core::Error::method_not_found()),
+        })
+        .await;
+        let client = EvmClient::new("Test".to_owned(), Web3::new(Http::new(&url).unwrap()));
+
+        let address = H160::from_low_u64_be(1);
+        assert_eq!(true, client.is_visual_erc721(address).await.unwrap());
+        assert_eq!(false, client.is_visual_erc1155(address).await.unwrap());
+        assert!(client.contract_cache().get(&address).unwrap().fingerprint.is_some());
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_preclassify_contracts_once() {
+        let a = H160::from_low_u64_be(1);
+        let b = H160::from_low_u64_be(2);
+        let events = vec![
+            erc721_event(a, H160::from_low_u64_be(11)),
+            erc721_event(a, H160::from_low_u64_be(12)),
+            erc1155_event(b),
+            erc1155_event(b),
+        ];
+        let classifier = CountingClassifier::default();
+        preclassify_contracts(&classifier, &events, 4).await;
+
+        let mut calls = classifier.calls.lock().unwrap().clone();
+        calls.sort();
+        assert_eq!(vec![a, b], calls);
+    }
+}
@@ -0,0 +1,175 @@
+//! This module contains a cache of what is known about the contracts seen by the tracker.
+//! Upgradeable proxies can change their interfaces and metadata logic under the same address,
+//! so the cached entry of a contract is invalidated when the contract is upgraded.
+//! The cache keeps a bounded number of contracts, the least recently used one is evicted first.
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+use web3::types::H160;
+use crate::ContractFingerprint;
+
+/// The cached classification and metadata of a contract.
+/// A `None` field has not been fetched yet.
+#[derive(Debug, Clone, Default)]
+pub struct ContractInfo {
+    /// Is an ERC721 contract which supports the metadata extension
+    pub visual_erc721: Option<bool>,
+    /// Is an ERC1155 contract which supports the metadata extension
+    pub visual_erc1155: Option<bool>,
+    /// Supports ERC5192
+    pub erc5192: Option<bool>,
+    /// The metadata name of an ERC721 contract
+    pub name: Option<String>,
+    /// The metadata symbol of an ERC721 contract
+    pub symbol: Option<String>,
+    /// The capabilities found in the bytecode
+    pub fingerprint: Option<ContractFingerprint>,
+}
+
+/// The default maximum number of contracts in the cache
+pub const DEFAULT_CONTRACT_CACHE_SIZE: usize = 10_000;
+
+/// The contract cache, shared by all the clones of an `EvmClient`
+#[derive(Debug, Clone)]
+pub struct ContractCache {
+    entries: Arc<Mutex<Entries>>,
+    capacity: usize,
+}
+
+/// The cached contracts with the time of their last use
+#[derive(Debug, Default)]
+struct Entries {
+    contracts: HashMap<H160, (ContractInfo, u64)>,
+    /// The contracts by the time of their last use, the least recently used first
+    uses: BTreeMap<u64, H160>,
+    clock: u64,
+}
+
+impl Entries {
+    /// The entry of a contract, which becomes the most recently used
+    fn use_entry(&mut self, address: &H160) -> Option<&mut ContractInfo> {
+        let (info, last_use) = self.contracts.get_mut(address)?;
+        self.clock += 1;
+        self.uses.remove(last_use);
+        self.uses.insert(self.clock, *address);
+        *last_use = self.clock;
+        Some(info)
+    }
+
+    /// Add an empty entry, the least recently used contracts are evicted to keep at most `capacity` contracts
+    fn insert(&mut self, address: H160, capacity: usize) {
+        while self.contracts.len() >= capacity.max(1) {
+            let (&last_use, &oldest) = self.uses.iter().next().expect("one use by contract");
+            self.uses.remove(&last_use);
+            self.contracts.remove(&oldest);
+        }
+        self.clock += 1;
+        self.uses.insert(self.clock, address);
+        self.contracts.insert(address, (ContractInfo::default(), self.clock));
+    }
+
+    fn remove(&mut self, address: &H160) -> bool {
+        match self.contracts.remove(address) {
+            Some((_, last_use)) => {
+                self.uses.remove(&last_use);
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+impl Default for ContractCache {
+    fn default() -> Self {
+        ContractCache::new(DEFAULT_CONTRACT_CACHE_SIZE)
+    }
+}
+
+impl ContractCache {
+    /// A cache of at most `capacity` contracts
+    pub fn new(capacity: usize) -> ContractCache {
+        ContractCache {
+            entries: Arc::new(Mutex::new(Entries::default())),
+            capacity,
+        }
+    }
+
+    /// Get what is known about a contract
+    pub fn get(&self, address: &H160) -> Option<ContractInfo> {
+        self.entries.lock().unwrap().use_entry(address).cloned()
+    }
+
+    /// Update the cached entry of a contract, an empty entry is created if the contract is unknown
+    pub fn update<F: FnOnce(&mut ContractInfo)>(&self, address: H160, f: F) {
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contracts.contains_key(&address) {
+            entries.insert(address, self.capacity);
+        }
+        f(entries.use_entry(&address).expect("inserted"));
+    }
+
+    /// Forget everything known about a contract.
+    /// Returns `true` if the contract was known.
+    pub fn invalidate(&self, address: &H160) -> bool {
+        self.entries.lock().unwrap().remove(address)
+    }
+
+    /// The number of known contracts
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().contracts.len()
+    }
+
+    /// Returns `true` if no contract is known
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_and_invalidate() {
+        let cache = ContractCache::default();
+        let address = H160::from_low_u64_be(1);
+        assert!(cache.get(&address).is_none());
+
+        cache.update(address, |info| info.visual_erc721 = Some(true));
+        cache.update(address, |info| info.name = Some("Hello".to_owned()));
+        let info = cache.get(&address).unwrap();
+        assert_eq!(Some(true), info.visual_erc721);
+        assert_eq!(Some("Hello".to_owned()), info.name);
+        assert_eq!(None, info.symbol);
+
+        // clones share the same entries
+        let clone = cache.clone();
+        assert_eq!(1, clone.len());
+        assert_eq!(true, clone.invalidate(&address));
+        assert!(cache.get(&address).is_none());
+        assert_eq!(false, cache.invalidate(&address));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let cache = ContractCache::new(2);
+        let contract = |i: u64| H160::from_low_u64_be(i);
+        cache.update(contract(1), |info| info.erc5192 = Some(true));
+        cache.update(contract(2), |info| info.erc5192 = Some(false));
+        // the contract 1 is used again, the contract 2 is evicted
+        assert!(cache.get(&contract(1)).is_some());
+        cache.update(contract(3), |info| info.erc5192 = Some(true));
+        assert_eq!(2, cache.len());
+        assert!(cache.get(&contract(2)).is_none());
+        assert_eq!(Some(true), cache.get(&contract(1)).unwrap().erc5192);
+        assert!(cache.get(&contract(3)).is_some());
+
+        // an invalidated contract makes room
+        assert!(cache.invalidate(&contract(1)));
+        cache.update(contract(4), |_| {});
+        assert!(cache.get(&contract(3)).is_some());
+        assert_eq!(2, cache.len());
+    }
+}
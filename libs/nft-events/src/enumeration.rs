@@ -0,0 +1,151 @@
+//! This module enumerates the tokens of ERC721Enumerable collections.
+//! The tracker only learns about the tokens which are transferred, so the tokens of an old
+//! collection are only partially known until they all move. The enumeration takes a
+//! snapshot of the whole collection instead.
+use crate::{Error, EvmClient, Result};
+use futures::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use web3::types::{H160, U256};
+
+/// A token found by the enumeration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Erc721Token {
+    /// The block at which the snapshot was taken
+    pub block_number: u64,
+    /// The ERC721 contract address
+    #[serde(with = "crate::schema::checksum_address")]
+    pub address: H160,
+    /// The token
+    #[serde(with = "crate::schema::u256_dec")]
+    pub token_id: U256,
+    /// The owner of the token
+    #[serde(with = "crate::schema::checksum_address")]
+    pub owner: H160,
+    /// The metadata token_uri of the token
+    pub token_uri: String,
+}
+
+/// When a token is enumerated, it will be exposed to the caller through this trait.
+/// The metadata of the collection is also passed along with it.
+#[async_trait]
+pub trait Erc721TokenCallback: Send {
+    /// The callback function
+    async fn on_erc721_token(
+        &mut self,
+        token: Erc721Token,
+        name: String,
+        symbol: String,
+    );
+}
+
+/// Enumerate all the tokens of an ERC721Enumerable collection with `totalSupply` and `tokenByIndex`.
+/// All the reads are pinned to `block`, or to the latest block if it is `None`, so the snapshot is consistent.
+/// Up to `concurrency` tokens are fetched at the same time, the tokens are streamed in index order.
+pub async fn snapshot_collection<'a>(
+    client: &'a EvmClient,
+    address: H160,
+    concurrency: usize,
+    block: Option<u64>,
+) -> Result<impl Stream<Item = Result<Erc721Token>> + 'a> {
+    let block = match block {
+        Some(block) => block,
+        None => client.get_latest_block_number().await?,
+    };
+    let total_supply = client.get_erc721_total_supply(&address, Some(block)).await?;
+    let total_supply = to_count(total_supply)?;
+
+    Ok(stream::iter(0..total_supply)
+        .map(move |index| async move {
+            let token_id = client
+                .get_erc721_token_by_index(&address, &U256::from(index), Some(block))
+                .await?;
+            let owner = client.get_erc721_owner_of(&address, &token_id, Some(block)).await?;
+            get_token(client, address, token_id, owner, block).await
+        })
+        .buffered(concurrency.max(1)))
+}
+
+/// Enumerate the tokens owned by an account in an ERC721Enumerable collection with `balanceOf` and `tokenOfOwnerByIndex`.
+/// All the reads are pinned to `block`, or to the latest block if it is `None`, so the snapshot is consistent.
+/// Up to `concurrency` tokens are fetched at the same time, the tokens are streamed in index order.
+pub async fn snapshot_owner_tokens<'a>(
+    client: &'a EvmClient,
+    address: H160,
+    owner: H160,
+    concurrency: usize,
+    block: Option<u64>,
+) -> Result<impl Stream<Item = Result<Erc721Token>> + 'a> {
+    let block = match block {
+        Some(block) => block,
+        None => client.get_latest_block_number().await?,
+    };
+    let balance = client.get_erc721_balance_of(&address, &owner, Some(block)).await?;
+    let balance = to_count(balance)?;
+
+    Ok(stream::iter(0..balance)
+        .map(move |index| async move {
+            let token_id = client
+                .get_erc721_token_of_owner_by_index(&address, &owner, &U256::from(index), Some(block))
+                .await?;
+            get_token(client, address, token_id, owner, block).await
+        })
+        .buffered(concurrency.max(1)))
+}
+
+async fn get_token(client: &EvmClient, address: H160, token_id: U256, owner: H160, block_number: u64) -> Result<Erc721Token> {
+    let token_uri = client.get_erc721_token_uri(&address, &token_id, Some(block_number)).await?;
+    Ok(Erc721Token {
+        block_number,
+        address,
+        token_id,
+        owner,
+        token_uri,
+    })
+}
+
+fn to_count(value: U256) -> Result<u64> {
+    if value > U256::from(u64::MAX) {
+        return Err(Error::Other(format!("Too many tokens to enumerate: {}", value)));
+    }
+    Ok(value.as_u64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc_stub;
+    use std::sync::{Arc, Mutex};
+    use web3::{transports::Http, Web3};
+
+    #[tokio::test]
+    async fn test_snapshot_is_pinned_to_block() {
+        // a collection of 2 tokens, the token ids are the indexes + 10
+        let blocks = Arc::new(Mutex::new(vec![]));
+        let called = blocks.clone();
+        let url = rpc_stub::serve(move |method, params| {
+            assert_eq!("eth_call", method);
+            called.lock().unwrap().push(params[1].clone());
+            let data = params[0]["data"].as_str().unwrap().to_owned();
+            let argument = u64::from_str_radix(&data[data.len().max(26) - 16..], 16).unwrap_or(0);
+            Ok(match &data[..10] {
+                "0x18160ddd" => rpc_stub::encode_word(2),
+                "0x4f6ccce7" => rpc_stub::encode_word(argument + 10),
+                "0x6352211e" => rpc_stub::encode_word(7),
+                "0xc87b56dd" => rpc_stub::encode_string(&format!("ipfs://{}", argument)),
+                selector => panic!("unexpected call {}", selector),
+            })
+        })
+        .await;
+        let client = EvmClient::new("Test".to_owned(), Web3::new(Http::new(&url).unwrap()));
+
+        let tokens = snapshot_collection(&client, H160::repeat_byte(1), 2, Some(100)).await.unwrap();
+        let tokens: Vec<Erc721Token> = tokens.map(|token| token.unwrap()).collect().await;
+        assert_eq!(vec![U256::from(10), U256::from(11)], tokens.iter().map(|t| t.token_id).collect::<Vec<_>>());
+        assert_eq!("ipfs://11", tokens[1].token_uri);
+        assert_eq!(H160::from_low_u64_be(7), tokens[1].owner);
+        // every read, the token URIs too, is at block 100
+        let blocks = blocks.lock().unwrap();
+        assert_eq!(7, blocks.len());
+        assert!(blocks.iter().all(|block| block == "0x64"));
+    }
+}
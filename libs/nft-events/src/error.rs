@@ -1,5 +1,36 @@
 #![allow(missing_docs)]
+use std::time::Duration;
 use thiserror::Error;
+use web3::types::{H160, H256};
+
+/// The errors of the providers refusing a `eth_getLogs` over too many blocks or results: their
+/// code and the fixed start of their message, which goes on with the limit of the provider
+const RANGE_TOO_LARGE_ERRORS: [(i64, &str); 4] = [
+    // Infura and the Frontier nodes, e.g. "query returned more than 10000 results"
+    (-32005, "query returned more than "),
+    // Alchemy, "Log response size exceeded. You can make eth_getLogs requests with up to a 2K block range ..."
+    (-32602, "Log response size exceeded."),
+    // Bor and Erigon, e.g. "exceed maximum block range: 5000"
+    (-32000, "exceed maximum block range: "),
+    // Moonbeam, e.g. "block range is too wide (maximum 1024)"
+    (-32603, "block range is too wide "),
+];
+
+/// The codes of the errors of the providers refusing a request over their rate limit: `-32005` is the
+/// "Limit exceeded" of EIP-1474, `429` is the status of the responses refused by the `HttpEndpoint`
+const RATE_LIMITED_CODES: [i64; 2] = [-32005, 429];
+
+/// The message of a reverted call, followed by its reason if any
+const REVERTED_MESSAGE: &str = "execution reverted";
+
+/// The codes of the errors raised by the EVM while executing a call, `3` of EIP-1474 and `-32015` of OpenEthereum
+const EXECUTION_ERROR_CODES: [i64; 2] = [3, -32015];
+
+/// The fields of the error data in which the providers ask for a delay, in seconds
+const RETRY_AFTER_FIELDS: [&str; 3] = ["backoff_seconds", "retry_after", "retryAfter"];
+
+/// The phrases of the error messages followed by the delay asked by the provider, in seconds
+const RETRY_AFTER_PHRASES: [&str; 2] = ["retry after", "try again in"];
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -12,6 +43,316 @@ pub enum Error {
         #[from]
         web3::contract::Error,
     ),
+    /// The node could not be reached or did not answer, the same request may succeed later
+    #[error("Transient RPC error in {context}: {source}")]
+    TransientRpcError { context: String, source: web3::Error },
+    /// The node answered the request with an error
+    #[error("RPC error in {context}: {source}")]
+    RpcError { context: String, source: web3::Error },
+    /// The node did not answer in time, the same request may succeed later
+    #[error("Timeout in {context}")]
+    Timeout { context: String },
+    /// The provider refused the request over its rate limit
+    #[error("Rate limited in {context}, retry after {retry_after:?}")]
+    RateLimited { context: String, retry_after: Option<Duration> },
+    /// The provider refused the logs of a range of blocks, a smaller range may succeed
+    #[error("The block range {from} - {to} is too large: {message}")]
+    RangeTooLarge { from: u64, to: u64, message: String },
+    /// The contract call reverted or returned nothing, the function is not implemented by the contract
+    #[error("The call of {method} on {address:?} reverted: {message}")]
+    RevertedCall { address: H160, method: String, message: String },
+    /// The EVM failed to execute the call, e.g. out of gas, the contract is at fault and not the node
+    #[error("The call of {method} on {address:?} failed: {message}")]
+    ExecutionError { address: H160, method: String, message: String },
+    /// A log with the topic of an NFT event but an invalid layout
+    #[error("Decode error: {reason}, log {log_index:?} of transaction {transaction_hash:?} of contract {address:?}")]
+    DecodeError {
+        address: H160,
+        block_number: Option<u64>,
+        transaction_hash: Option<H256>,
+        log_index: Option<u64>,
+        reason: String,
+    },
+    /// The sink did not acknowledge a batch
+    #[error("The sink failed on {from} - {to} of {cursor} after {attempts} attempts: {reason}")]
+    SinkError { cursor: String, from: u64, to: u64, attempts: u32, reason: String },
+    /// The current range was not done within the drain timeout after a shutdown, it was aborted
+    #[error("The current range was not done within the drain timeout of {timeout:?}, it was aborted")]
+    DrainTimeout { timeout: Duration },
+    /// The tracker was run while it was already running
+    #[error("The tracker is already running")]
+    AlreadyRunning,
+    /// The stream of the tracked events was dropped while the tracking was running
+    #[error("The stream of the tracked events is closed")]
+    StreamClosed,
+    /// The configuration of the tracker is invalid
+    #[error("Invalid config: {0}")]
+    ConfigError(String),
     #[error("Other error: {0}")]
     Other(String),
 }
+
+/// What the tracker does about an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// Send the same request again later
+    Retry,
+    /// Send the same request again after the delay asked by the provider
+    RetryAfter(Duration),
+    /// Fetch the logs of the range in smaller parts
+    SplitRange,
+    /// Skip the log or the call, retrying gives the same error
+    Skip,
+    /// Stop the tracking with the error
+    Halt,
+}
+
+impl Error {
+    /// Classify an error of the node, `context` tells which request failed
+    pub fn rpc<S: Into<String>>(err: web3::Error, context: S) -> Error {
+        if let Some(retry_after) = rate_limit(&err) {
+            return Error::RateLimited {
+                context: context.into(),
+                retry_after,
+            };
+        }
+        match &err {
+            web3::Error::Io(e) if e.kind() == std::io::ErrorKind::TimedOut => Error::Timeout {
+                context: context.into(),
+            },
+            web3::Error::Unreachable | web3::Error::Transport(_) | web3::Error::Io(_) => Error::TransientRpcError {
+                context: context.into(),
+                source: err,
+            },
+            _ => Error::RpcError {
+                context: context.into(),
+                source: err,
+            },
+        }
+    }
+
+    /// Classify an error of `eth_getLogs` over `from` - `to`
+    pub fn logs(err: web3::Error, from: u64, to: u64) -> Error {
+        match err {
+            web3::Error::Rpc(e) if is_range_too_large(&e) => Error::RangeTooLarge { from, to, message: e.message },
+            err => Error::rpc(err, format!("eth_getLogs {} - {}", from, to)),
+        }
+    }
+
+    /// Classify an error of the call of `method` on the contract at `address`
+    pub fn call(err: web3::contract::Error, address: H160, method: &str) -> Error {
+        let reverted = |message: String| Error::RevertedCall {
+            address,
+            method: method.to_owned(),
+            message,
+        };
+        match err {
+            // nothing was returned to decode
+            web3::contract::Error::Abi(web3::ethabi::Error::InvalidName(name)) => reverted(name),
+            web3::contract::Error::Api(web3::Error::Rpc(e)) if is_reverted(&e) => {
+                reverted(e.message)
+            },
+            web3::contract::Error::Api(web3::Error::Rpc(e)) if is_execution_error(&e) => Error::ExecutionError {
+                address,
+                method: method.to_owned(),
+                message: e.message,
+            },
+            web3::contract::Error::Api(err) => Error::rpc(err, format!("{} of {:?}", method, address)),
+            err => Error::Web3ContractError(err),
+        }
+    }
+
+    /// The node or the provider failed, not the request: the same request may succeed later
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::TransientRpcError { .. } | Error::Timeout { .. } | Error::RateLimited { .. }
+        )
+    }
+
+    /// The log or its contract is at fault, not the node: the other logs of the range are tracked without it.
+    /// The errors of the node, e.g. a missing block or state, are the same for every log.
+    pub fn is_log_error(&self) -> bool {
+        matches!(
+            self,
+            Error::DecodeError { .. }
+                | Error::RevertedCall { .. }
+                | Error::ExecutionError { .. }
+                | Error::Web3EthabiError(_)
+                | Error::Web3ContractError(_)
+        )
+    }
+
+    /// What the tracker does about this error.
+    /// The errors which are not classified are retried.
+    pub fn recovery(&self) -> Recovery {
+        match self {
+            Error::RateLimited { retry_after: Some(delay), .. } => Recovery::RetryAfter(*delay),
+            Error::RangeTooLarge { .. } => Recovery::SplitRange,
+            Error::RevertedCall { .. } | Error::DecodeError { .. } => Recovery::Skip,
+            // the ABI or the output of the contract can not be decoded
+            Error::Web3EthabiError(_) | Error::Web3ContractError(_) => Recovery::Skip,
+            Error::SinkError { .. } | Error::ConfigError(_) => Recovery::Halt,
+            Error::DrainTimeout { .. } | Error::AlreadyRunning | Error::StreamClosed => Recovery::Halt,
+            _ => Recovery::Retry,
+        }
+    }
+}
+
+fn is_range_too_large(err: &jsonrpc_core::Error) -> bool {
+    RANGE_TOO_LARGE_ERRORS
+        .iter()
+        .any(|(code, message)| err.code == jsonrpc_core::ErrorCode::ServerError(*code) && err.message.starts_with(message))
+}
+
+fn is_reverted(err: &jsonrpc_core::Error) -> bool {
+    err.message == REVERTED_MESSAGE || err.message.starts_with(&format!("{}: ", REVERTED_MESSAGE))
+}
+
+fn is_execution_error(err: &jsonrpc_core::Error) -> bool {
+    EXECUTION_ERROR_CODES.iter().any(|code| err.code == jsonrpc_core::ErrorCode::ServerError(*code))
+}
+
+fn is_rate_limited(err: &jsonrpc_core::Error) -> bool {
+    RATE_LIMITED_CODES.iter().any(|code| err.code == jsonrpc_core::ErrorCode::ServerError(*code))
+}
+
+/// `Some` if the provider refused the request over its rate limit, with the delay it asked for if any.
+/// The `HttpEndpoint` returns a `429` status as an RPC error of code `429`, with the delay of its
+/// `Retry-After` header in the `retry_after` field of its data.
+pub(crate) fn rate_limit(err: &web3::Error) -> Option<Option<Duration>> {
+    match err {
+        web3::Error::Rpc(e) if is_rate_limited(e) => Some(retry_after(e)),
+        _ => None,
+    }
+}
+
+/// The delay asked in the error data, e.g. `backoff_seconds` of Infura, or in the message
+fn retry_after(err: &jsonrpc_core::Error) -> Option<Duration> {
+    let seconds = |value: &serde_json::Value| value.as_f64().filter(|s| s.is_finite() && *s >= 0.0);
+    let from_data = err
+        .data
+        .as_ref()
+        .and_then(|data| RETRY_AFTER_FIELDS.iter().find_map(|field| data.get(field).and_then(seconds)));
+    let from_message = || {
+        let message = err.message.to_lowercase();
+        RETRY_AFTER_PHRASES.iter().find_map(|phrase| {
+            let rest = &message[message.find(phrase)? + phrase.len()..];
+            let number: String = rest.trim_start().chars().take_while(|c| c.is_ascii_digit() || *c == '.').collect();
+            number.parse::<f64>().ok()
+        })
+    };
+    from_data.or_else(from_message).map(Duration::from_secs_f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonrpc_core::ErrorCode;
+
+    fn rpc_error(code: i64, message: &str) -> web3::Error {
+        web3::Error::Rpc(jsonrpc_core::Error {
+            code: ErrorCode::ServerError(code),
+            message: message.to_owned(),
+            data: None,
+        })
+    }
+
+    #[test]
+    fn test_rpc_errors() {
+        let err = Error::rpc(web3::Error::Unreachable, "eth_blockNumber");
+        assert!(matches!(err, Error::TransientRpcError { ref context, .. } if context == "eth_blockNumber"));
+        assert_eq!(Recovery::Retry, err.recovery());
+
+        let err = Error::rpc(rpc_error(-32005, "Daily request count exceeded, Request rate limited"), "eth_call");
+        assert!(matches!(err, Error::RateLimited { retry_after: None, .. }));
+        assert_eq!(Recovery::Retry, err.recovery());
+
+        let err = Error::rpc(rpc_error(-32005, "Too many requests, retry after 3 seconds"), "eth_call");
+        assert_eq!(Recovery::RetryAfter(Duration::from_secs(3)), err.recovery());
+
+        let infura = web3::Error::Rpc(jsonrpc_core::Error {
+            code: ErrorCode::ServerError(-32005),
+            message: "project ID request rate exceeded".to_owned(),
+            data: Some(serde_json::json!({ "current_rps": 13.3, "allowed_rps": 10.0, "backoff_seconds": 1.5 })),
+        });
+        let err = Error::rpc(infura, "eth_getLogs");
+        assert_eq!(Recovery::RetryAfter(Duration::from_millis(1500)), err.recovery());
+
+        let err = Error::logs(rpc_error(-32005, "query returned more than 10000 results"), 100, 200);
+        assert!(matches!(err, Error::RangeTooLarge { from: 100, to: 200, .. }));
+        assert_eq!(Recovery::SplitRange, err.recovery());
+
+        let alchemy = "Log response size exceeded. You can make eth_getLogs requests with up to a 2K block range";
+        let err = Error::logs(rpc_error(-32602, alchemy), 100, 200);
+        assert!(matches!(err, Error::RangeTooLarge { .. }));
+
+        // the same words in another error
+        let err = Error::logs(rpc_error(-32602, "invalid block range params"), 100, 200);
+        assert!(matches!(err, Error::RpcError { .. }));
+        let err = Error::logs(rpc_error(-32000, "query returned more than 10000 results"), 100, 200);
+        assert!(matches!(err, Error::RpcError { .. }));
+        let err = Error::rpc(rpc_error(-32000, "rate limit of the block gas exceeded"), "eth_call");
+        assert!(matches!(err, Error::RpcError { .. }));
+
+        let timed_out = std::io::Error::new(std::io::ErrorKind::TimedOut, "no answer");
+        let err = Error::logs(web3::Error::Io(timed_out), 100, 200);
+        assert!(matches!(err, Error::Timeout { ref context } if context == "eth_getLogs 100 - 200"));
+        assert!(err.is_transient());
+        assert_eq!(Recovery::Retry, err.recovery());
+
+        let err = Error::logs(rpc_error(-32000, "header not found"), 100, 200);
+        assert!(matches!(err, Error::RpcError { ref context, .. } if context == "eth_getLogs 100 - 200"));
+        assert_eq!(Recovery::Retry, err.recovery());
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let address = H160::repeat_byte(1);
+        let err = Error::call(web3::contract::Error::Api(rpc_error(3, "execution reverted")), address, "tokenURI");
+        assert!(matches!(err, Error::RevertedCall { address: a, ref method, .. } if a == address && method == "tokenURI"));
+        assert_eq!(Recovery::Skip, err.recovery());
+        let err = Error::call(web3::contract::Error::Api(rpc_error(-32000, "execution reverted: burned")), address, "tokenURI");
+        assert!(matches!(err, Error::RevertedCall { .. }));
+
+        let err = Error::call(web3::contract::Error::Api(web3::Error::Unreachable), address, "tokenURI");
+        assert!(matches!(err, Error::TransientRpcError { .. }));
+        assert!(!err.is_log_error());
+
+        let err = Error::call(web3::contract::Error::Api(rpc_error(-32015, "VM execution error.")), address, "tokenURI");
+        assert!(matches!(err, Error::ExecutionError { .. }));
+        assert!(err.is_log_error());
+        assert_eq!(Recovery::Retry, err.recovery());
+
+        // the node misses the state of the block, every call fails
+        let err = Error::call(web3::contract::Error::Api(rpc_error(-32000, "missing trie node")), address, "locked");
+        assert!(matches!(err, Error::RpcError { .. }));
+        assert!(!err.is_log_error());
+
+        let err = Error::call(web3::contract::Error::InvalidOutputType("bool".to_owned()), address, "supportsInterface");
+        assert!(matches!(err, Error::Web3ContractError(_)));
+        assert_eq!(Recovery::Skip, err.recovery());
+    }
+
+    #[test]
+    fn test_recovery() {
+        let rate_limited = Error::RateLimited {
+            context: "eth_getLogs".to_owned(),
+            retry_after: Some(Duration::from_secs(2)),
+        };
+        assert_eq!(Recovery::RetryAfter(Duration::from_secs(2)), rate_limited.recovery());
+
+        let sink = Error::SinkError {
+            cursor: "main".to_owned(),
+            from: 1,
+            to: 2,
+            attempts: 3,
+            reason: "unavailable".to_owned(),
+        };
+        assert_eq!(Recovery::Halt, sink.recovery());
+        assert_eq!(Recovery::Halt, Error::ConfigError("step must be positive".to_owned()).recovery());
+        assert_eq!(Recovery::Halt, Error::DrainTimeout { timeout: Duration::from_secs(30) }.recovery());
+        assert_eq!(Recovery::Halt, Error::StreamClosed.recovery());
+        assert_eq!(Recovery::Retry, Error::Other("unknown".to_owned()).recovery());
+    }
+}
@@ -0,0 +1,162 @@
+//! This module exposes the tracked events as a `Stream`.
+//! The stream drives the tracking itself, nothing is fetched while it is not polled. The events of
+//! a range are yielded in order and the checkpoint of the range is saved when the event after its
+//! last one is asked for, a stream dropped or cancelled in the middle of a range replays the range
+//! on the next run.
+use crate::{
+    sink::{EventBatch, EventSink, TrackedEvent},
+    tracker::{self, Cursor},
+    CheckpointStore, Error, EventsConfig, EvmClient, Result, TrackerConfig,
+};
+use futures::{
+    future::{self, BoxFuture, Future, FutureExt},
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, watch};
+
+/// Stops a stream of tracked events, shared by all its clones.
+/// The stream ends after the event it is yielding, the checkpoints stay at the last fully taken range.
+#[derive(Debug, Clone)]
+pub struct CancelHandle {
+    sender: Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+}
+
+impl CancelHandle {
+    /// A handle which is not cancelled yet
+    pub fn new() -> CancelHandle {
+        let (sender, receiver) = watch::channel(false);
+        CancelHandle {
+            sender: Arc::new(sender),
+            receiver,
+        }
+    }
+
+    /// Stop the streams of this handle
+    pub fn cancel(&self) {
+        let _ = self.sender.send(true);
+    }
+
+    /// Returns `true` if `cancel` was called
+    pub fn is_cancelled(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Resolves once `cancel` is called
+    pub async fn cancelled(&self) {
+        let mut receiver = self.receiver.clone();
+        while !*receiver.borrow() {
+            if receiver.changed().await.is_err() {
+                // the handle is gone, it can not be cancelled anymore
+                future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+impl Default for CancelHandle {
+    fn default() -> Self {
+        CancelHandle::new()
+    }
+}
+
+/// What the tracking sends to the stream
+enum Delivery {
+    Event(TrackedEvent),
+    /// Acknowledged when the stream is asked for the next event
+    Ack(oneshot::Sender<()>),
+}
+
+/// Forwards the events of the batches to the stream
+struct ChannelSink {
+    sender: mpsc::Sender<Delivery>,
+}
+
+#[async_trait]
+impl EventSink for ChannelSink {
+    async fn on_batch(&mut self, batch: &EventBatch) -> Result<()> {
+        for tracked in &batch.events {
+            self.sender.send(Delivery::Event(tracked.clone())).await.map_err(|_| Error::StreamClosed)?;
+        }
+
+        let (ack, acked) = oneshot::channel();
+        self.sender.send(Delivery::Ack(ack)).await.map_err(|_| Error::StreamClosed)?;
+        acked.await.map_err(|_| Error::StreamClosed)
+    }
+}
+
+/// Track the events as a stream, see `tracker::track_events` for the cursors of `tracker_config`.
+/// The stream ends when the cursors with an end are done or when `cancel` is called on the
+/// returned handle, which is the `shutdown` of `tracker_config`. It yields the error of the
+/// tracking before it ends, if any.
+pub fn track_stream<'a>(
+    evm_client: &'a EvmClient,
+    config: &'a EventsConfig,
+    tracker_config: &'a TrackerConfig,
+    checkpoints: &'a dyn CheckpointStore,
+) -> (BoxStream<'a, Result<TrackedEvent>>, CancelHandle) {
+    let cursors = async move { Ok((tracker::tracker_cursors(evm_client, tracker_config, checkpoints).await?, false)) };
+    let (events, tracking) = tracked_events(evm_client, config, tracker_config, cursors, checkpoints);
+
+    // The tracking only yields its error, the events come through the channel
+    let tracking = stream::once(tracking).filter_map(|result| future::ready(result.err().map(Err)));
+    let events = events.map(Ok);
+
+    let handle = tracker_config.shutdown.clone();
+    let cancelled = handle.clone();
+    let stream = stream::select(events, tracking)
+        .take_until(async move { cancelled.cancelled().await })
+        .boxed();
+    (stream, handle)
+}
+
+/// The events of the cursors returned by `cursors`, in order if its flag is set, and the tracking
+/// which fetches them. The events only come while the tracking is polled, the stream ends with it.
+pub(crate) fn tracked_events<'a, F>(
+    evm_client: &'a EvmClient,
+    config: &'a EventsConfig,
+    tracker_config: &'a TrackerConfig,
+    cursors: F,
+    checkpoints: &'a dyn CheckpointStore,
+) -> (BoxStream<'a, TrackedEvent>, BoxFuture<'a, Result<()>>)
+where
+    F: Future<Output = Result<(Vec<Cursor>, bool)>> + Send + 'a,
+{
+    let (sender, receiver) = mpsc::channel(1);
+    let tracking = async move {
+        let (cursors, ordered) = cursors.await?;
+        let mut sink = ChannelSink { sender };
+        tracker::run_cursors(evm_client, config, tracker_config, cursors, ordered, checkpoints, &mut sink).await
+    };
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await? {
+                Delivery::Event(tracked) => return Some((tracked, receiver)),
+                Delivery::Ack(ack) => {
+                    let _ = ack.send(());
+                },
+            }
+        }
+    });
+    (events.boxed(), tracking.boxed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_cancel_handle() {
+        let handle = CancelHandle::new();
+        let clone = handle.clone();
+        assert_eq!(false, handle.is_cancelled());
+        assert!(tokio::time::timeout(Duration::from_millis(10), handle.cancelled()).await.is_err());
+
+        clone.cancel();
+        assert_eq!(true, handle.is_cancelled());
+        tokio::time::timeout(Duration::from_millis(10), handle.cancelled()).await.unwrap();
+    }
+}
@@ -1,141 +1,511 @@
-use crate::{EvmClient, Result, Error};
+use crate::{classifier, EvmClient, Result, Error, Recovery, TopicFilter, Watchlist};
 use array_bytes::hex2bytes_unchecked as bytes;
-use jsonrpc_core::error;
-use web3::types::{Bytes, Log, H160, H256, U256};
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    str::FromStr,
+};
+use web3::{
+    ethabi::{self, EventParam, ParamType, RawLog, Token},
+    types::{Log, H160, H256, U256},
+};
 
-/// The Erc721 Transfer Event Wrapper
+/// The kind of a transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferKind {
+    /// Transfer from the zero address
+    Mint,
+    /// Transfer to the zero address or to a burn address
+    Burn,
+    /// Transfer between two different accounts
+    Transfer,
+    /// Transfer from an account to itself
+    SelfTransfer,
+}
+
+impl TransferKind {
+    /// Classify a transfer by its `from` and `to` addresses.
+    /// A transfer to one of the `burn_addresses` is a burn, as well as a transfer to the zero address.
+    pub fn classify(from: &H160, to: &H160, burn_addresses: &[H160]) -> TransferKind {
+        if from.is_zero() {
+            TransferKind::Mint
+        } else if to.is_zero() || burn_addresses.contains(to) {
+            TransferKind::Burn
+        } else if from == to {
+            TransferKind::SelfTransfer
+        } else {
+            TransferKind::Transfer
+        }
+    }
+
+    /// The name of the kind, used by the sinks
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransferKind::Mint => "mint",
+            TransferKind::Burn => "burn",
+            TransferKind::Transfer => "transfer",
+            TransferKind::SelfTransfer => "self_transfer",
+        }
+    }
+}
+
+/// The well-known burn address `0x000000000000000000000000000000000000dEaD`
+pub const DEAD_ADDRESS: &str = "0x000000000000000000000000000000000000dEaD";
+
+/// The default maximum number of addresses in one `eth_getLogs` filter
+pub const DEFAULT_MAX_ADDRESSES_PER_FILTER: usize = 500;
+
+/// The default maximum number of contract calls at the same time
+pub const DEFAULT_CONCURRENCY: usize = 16;
+
+/// The NFT standards whose events can be tracked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Standard {
+    /// The ERC721 `Transfer` events
+    Erc721,
+    /// The ERC1155 `TransferSingle` and `TransferBatch` events
+    Erc1155,
+    /// The ERC5192 `Locked` and `Unlocked` events
+    Erc5192,
+}
+
+impl Standard {
+    /// All the standards
+    pub fn all() -> BTreeSet<Standard> {
+        vec![Standard::Erc721, Standard::Erc1155, Standard::Erc5192].into_iter().collect()
+    }
+
+    /// The topics of the events of the standard
+    fn topics(&self) -> Vec<H256> {
+        match self {
+            Standard::Erc721 => vec![topic(ERC721_TRANSFER_TOPIC)],
+            Standard::Erc1155 => vec![topic(ERC1155_TRANSFER_SINGLE_TOPIC), topic(ERC1155_TRANSFER_BATCH_TOPIC)],
+            Standard::Erc5192 => vec![topic(ERC5192_LOCKED_TOPIC), topic(ERC5192_UNLOCKED_TOPIC)],
+        }
+    }
+}
+
+/// How the events are built
 #[derive(Debug, Clone)]
+pub struct EventsConfig {
+    /// The transfers to these addresses are burns, as well as the transfers to the zero address
+    pub burn_addresses: Vec<H160>,
+    /// Only the logs of these contracts are fetched, the addresses are sent in the `eth_getLogs` filter.
+    /// The logs of all the contracts are fetched if it is empty.
+    pub allowlist: Vec<H160>,
+    /// The logs of these contracts are skipped before any classification, e.g. known spam contracts
+    pub denylist: HashSet<H160>,
+    /// The maximum number of addresses in one `eth_getLogs` filter, a longer allowlist is split into several requests
+    pub max_addresses_per_filter: usize,
+    /// Only the transfers from or to the watched addresses are fetched if it is set, the wallet-centric tracking
+    pub watchlist: Option<Watchlist>,
+    /// The maximum number of contract calls at the same time, when classifying and fetching the metadata
+    pub concurrency: usize,
+    /// Only the events of these standards are fetched
+    pub standards: BTreeSet<Standard>,
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        EventsConfig {
+            burn_addresses: vec![H160::from_str(DEAD_ADDRESS).unwrap()],
+            allowlist: vec![],
+            denylist: HashSet::new(),
+            max_addresses_per_filter: DEFAULT_MAX_ADDRESSES_PER_FILTER,
+            watchlist: None,
+            concurrency: DEFAULT_CONCURRENCY,
+            standards: Standard::all(),
+        }
+    }
+}
+
+/// The Erc721 Transfer Event Wrapper
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Erc721Event {
     /// The block to which this event belongs
     pub block_number: Option<u64>,
+    /// The hash of the block to which this event belongs
+    pub block_hash: Option<H256>,
+    /// The timestamp of the block to which this event belongs, in seconds since the epoch
+    pub block_timestamp: Option<u64>,
     /// The ERC721 contract address
+    #[serde(with = "crate::schema::checksum_address")]
     pub address: H160,
     /// The transaction that issued this event
     pub transaction_hash: Option<H256>,
+    /// The index of the transaction in the block
+    pub transaction_index: Option<u64>,
+    /// The index of the log in the block, `(block_number, log_index)` orders the events of a chain
+    pub log_index: Option<u64>,
+    /// `true` if the log was removed by a chain reorganization
+    pub removed: bool,
     /// Transfer from
+    #[serde(with = "crate::schema::checksum_address")]
     pub from: H160,
     /// Transfer to
+    #[serde(with = "crate::schema::checksum_address")]
     pub to: H160,
     /// Transferred ERC721 token
+    #[serde(with = "crate::schema::u256_dec")]
     pub token_id: U256,
+    /// Mint, burn or transfer
+    pub kind: TransferKind,
+    /// Whether the token is soulbound, i.e. locked by an ERC5192 contract
+    pub soulbound: bool,
 }
 
 /// The Erc1155 Transfer Event Wrapper
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Erc1155Event {
     /// The block to which this event belongs
     pub block_number: Option<u64>,
+    /// The hash of the block to which this event belongs
+    pub block_hash: Option<H256>,
+    /// The timestamp of the block to which this event belongs, in seconds since the epoch
+    pub block_timestamp: Option<u64>,
     /// The ERC721 contract address
+    #[serde(with = "crate::schema::checksum_address")]
     pub address: H160,
     /// The transaction that issued this event
     pub transaction_hash: Option<H256>,
+    /// The index of the transaction in the block
+    pub transaction_index: Option<u64>,
+    /// The index of the log in the block, `(block_number, log_index)` orders the events of a chain
+    pub log_index: Option<u64>,
+    /// `true` if the log was removed by a chain reorganization
+    pub removed: bool,
     /// The address of an account/contract that is approved to make the transfer
+    #[serde(with = "crate::schema::checksum_address")]
     pub operator: H160,
     /// Transfer from
+    #[serde(with = "crate::schema::checksum_address")]
     pub from: H160,
     /// Transfer to
+    #[serde(with = "crate::schema::checksum_address")]
     pub to: H160,
     /// The token type being transferred
+    #[serde(with = "crate::schema::u256_dec")]
     pub token_id: U256,
     /// Number of the token transferred
+    #[serde(with = "crate::schema::u256_dec")]
     pub amount: U256,
+    /// Mint, burn or transfer
+    pub kind: TransferKind,
+    /// The position of the token in a `TransferBatch` event, 0 for a `TransferSingle` event
+    pub batch_index: u64,
 }
 
-/// Event
-#[derive(Debug, Clone)]
+/// The Erc5192 Locked/Unlocked Event Wrapper
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Erc5192Event {
+    /// The block to which this event belongs
+    pub block_number: Option<u64>,
+    /// The hash of the block to which this event belongs
+    pub block_hash: Option<H256>,
+    /// The timestamp of the block to which this event belongs, in seconds since the epoch
+    pub block_timestamp: Option<u64>,
+    /// The ERC5192 contract address
+    #[serde(with = "crate::schema::checksum_address")]
+    pub address: H160,
+    /// The transaction that issued this event
+    pub transaction_hash: Option<H256>,
+    /// The index of the transaction in the block
+    pub transaction_index: Option<u64>,
+    /// The index of the log in the block, `(block_number, log_index)` orders the events of a chain
+    pub log_index: Option<u64>,
+    /// `true` if the log was removed by a chain reorganization
+    pub removed: bool,
+    /// The token whose locking status changed
+    #[serde(with = "crate::schema::u256_dec")]
+    pub token_id: U256,
+    /// `true` for a `Locked` event, `false` for an `Unlocked` event
+    pub locked: bool,
+}
+
+/// Event, serialized with a `type` tag of `erc721`, `erc1155` or `erc5192`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum Event {
     /// Erc721Event
     Erc721(Erc721Event),
     /// Erc1155Event
-    Erc1155(Erc1155Event)
+    Erc1155(Erc1155Event),
+    /// Erc5192Event
+    Erc5192(Erc5192Event),
+}
+
+impl Event {
+    /// The block to which this event belongs
+    pub fn block_number(&self) -> Option<u64> {
+        match self {
+            Event::Erc721(e) => e.block_number,
+            Event::Erc1155(e) => e.block_number,
+            Event::Erc5192(e) => e.block_number,
+        }
+    }
+
+    /// The index of the log in the block
+    pub fn log_index(&self) -> Option<u64> {
+        match self {
+            Event::Erc721(e) => e.log_index,
+            Event::Erc1155(e) => e.log_index,
+            Event::Erc5192(e) => e.log_index,
+        }
+    }
+
+    fn set_block_timestamp(&mut self, timestamp: u64) {
+        match self {
+            Event::Erc721(e) => e.block_timestamp = Some(timestamp),
+            Event::Erc1155(e) => e.block_timestamp = Some(timestamp),
+            Event::Erc5192(e) => e.block_timestamp = Some(timestamp),
+        }
+    }
+}
+
+const ERC721_TRANSFER_TOPIC: &str = "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+const ERC1155_TRANSFER_SINGLE_TOPIC: &str = "0xc3d58168c5ae7397731d063d5bbf3d657854427343f4c083240f7aacaa2d0f62";
+const ERC1155_TRANSFER_BATCH_TOPIC: &str = "0x4a39dc06d4c0dbc64b70af90fd698a233a518aa5d07e595d983b8c0526c8f7fb";
+const ERC5192_LOCKED_TOPIC: &str = "0x032bc66be43dbccb7487781d168eb7bda224628a3b2c3388bdf69b532a3a1611";
+const ERC5192_UNLOCKED_TOPIC: &str = "0xf27b6ce5b2f5e68ddb2fd95a8a909d4ecf1daaac270935fff052feacb24f1842";
+const EIP1967_UPGRADED_TOPIC: &str = "0xbc7cd75a20ee27fd9adebab32041f755214dbc6bffa90cc0225b39da2e5c2d3b";
+const EIP1967_BEACON_UPGRADED_TOPIC: &str = "0x1cf3b03a6cf19fa2baba4df148e9dcabedea7f8a5c07840e207e5c089be95d3e";
+
+fn topic(hex: &str) -> H256 {
+    H256::from_slice(&bytes(hex))
 }
 
 /// Get all events between `from` and `to`.
 /// the `from` and `to` blocks are included.
-pub async fn get_events(client: &EvmClient, from: u64, to: u64) -> Result<Vec<Event>> {
-    let erc721_transfer_topic = H256::from_slice(&bytes(
-        "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef",
-    ));
-    let erc1155_transfer_single_topic = H256::from_slice(&bytes(
-        "0xc3d58168c5ae7397731d063d5bbf3d657854427343f4c083240f7aacaa2d0f62",
-    ));
-    let erc1155_transfer_batch_topic = H256::from_slice(&bytes(
-        "0x4a39dc06d4c0dbc64b70af90fd698a233a518aa5d07e595d983b8c0526c8f7fb",
-    ));
-    let topics = vec![
-        erc721_transfer_topic, 
-        erc1155_transfer_single_topic, 
-        erc1155_transfer_batch_topic
-    ];
-    let logs = client.get_logs(None, topics, from, to).await?;
+/// The logs are decoded with `decode_log` and classified by the `EvmClient`.
+pub async fn get_events(client: &EvmClient, config: &EventsConfig, from: u64, to: u64) -> Result<Vec<Event>> {
+    let logs = get_logs(client, config, from, to).await?;
+    get_events_of_logs(client, config, logs).await
+}
 
-    let mut result = vec![];
-    for log in logs {
+/// Decode and classify the logs into events, the logs which fail with an error to skip are skipped.
+/// Returns the first error of a log which is not skipped.
+pub(crate) async fn get_events_of_logs(client: &EvmClient, config: &EventsConfig, logs: Vec<Log>) -> Result<Vec<Event>> {
+    let concurrency = config.concurrency.max(1);
 
-        if let Err(err) = process_log(client, &log, erc721_transfer_topic, erc1155_transfer_single_topic, &mut result).await {
+    let mut decoded = vec![];
+    for log in logs {
+        match process_log(client, config, &log) {
+            Ok(events) => decoded.push((log, events)),
+            Err(err) => {
+                if let Some(e) = process_err(&log, err) {
+                    return Err(e);
+                }
+            },
+        }
+    }
 
-            if let Some(e) = process_err(&log, err) {
-                return Err(e);
-            }
+    // Each contract of the range is classified once, then the logs are classified
+    // at the same time and collected in their order
+    classifier::preclassify_contracts(client, decoded.iter().flat_map(|(_, events)| events), concurrency).await;
+    let classified: Vec<(Log, Result<Vec<Event>>)> = stream::iter(decoded)
+        .map(|(log, events)| async move {
+            let events = classifier::classify_events(client, config, events).await;
+            (log, events)
+        })
+        .buffered(concurrency)
+        .collect()
+        .await;
 
+    let mut result = vec![];
+    for (log, events) in classified {
+        match events {
+            Ok(mut events) => result.append(&mut events),
+            Err(err) => {
+                if let Some(e) = process_err(&log, err) {
+                    return Err(e);
+                }
+            },
         }
-
     }
 
+    set_block_timestamps(client, &mut result, concurrency).await?;
+
     Ok(result)
 }
 
-fn process_err(log: &Log, err: Error) -> Option<Error> {
-    match err {
-        Error::Web3ContractError(ref e1) => {
-            match e1 {
-
-                web3::contract::Error::Abi(web3::ethabi::Error::InvalidName(msg)) => {
-                    error!("{:?} >>> {}", log, msg);
-                    None
-                },
-
-                web3::contract::Error::Api(web3::Error::Rpc(jsonrpc_core::types::Error { code, message, data } )) => {
-                    if message == "execution reverted" {
-                        error!("{:?} >>> {:?}", log, err);
-                        None
-                    } else {
-                        Some(err)
-                    }
-                },
-
-                _ => Some(err),
-            }
+/// Get the logs of the allowlisted contracts, or of all the contracts if there is no allowlist.
+/// The allowlist is split into chunks of `max_addresses_per_filter` addresses, the logs of the
+/// chunks and of the topic filters are merged back into the chain order.
+pub(crate) async fn get_logs(client: &EvmClient, config: &EventsConfig, from: u64, to: u64) -> Result<Vec<Log>> {
+    let max_addresses = config.max_addresses_per_filter.max(1);
+    let contracts: Vec<Vec<H160>> = if config.allowlist.is_empty() {
+        vec![vec![]]
+    } else {
+        config.allowlist.chunks(max_addresses).map(|chunk| chunk.to_vec()).collect()
+    };
+
+    let mut logs = vec![];
+    for contract_addresses in contracts {
+        for topics in topic_filters(config) {
+            let mut chunk = client.get_filtered_logs(contract_addresses.clone(), topics, from, to).await?;
+            logs.append(&mut chunk);
+        }
+    }
+
+    merge_logs(&mut logs);
+    retain_watched_locks(config, &mut logs);
+    Ok(logs)
+}
+
+/// Sort the logs of several filters into the chain order and remove the duplicates: a transfer
+/// between two watched addresses matches two filters. Only the logs with a block hash, a
+/// transaction hash and a log index are known to be the same log, the others are all kept.
+fn merge_logs(logs: &mut Vec<Log>) {
+    logs.sort_by_key(|log| (log.block_number, log.log_index, log.block_hash, log.transaction_hash));
+    logs.dedup_by(|log, previous| {
+        let key = |log: &Log| (log.block_hash, log.transaction_hash, log.log_index);
+        matches!(key(log), (Some(_), Some(_), Some(_))) && key(log) == key(previous)
+    });
+}
+
+/// With a watchlist, only the ERC5192 `Locked` and `Unlocked` logs of the tokens transferred from or to
+/// the watched addresses in the same range are kept: they are fetched without an address filter.
+fn retain_watched_locks(config: &EventsConfig, logs: &mut Vec<Log>) {
+    if config.watchlist.is_none() {
+        return;
+    }
+
+    let watched: HashSet<(H160, H256)> = logs
+        .iter()
+        .filter(|log| log.topics.len() == 4 && log.topics[0] == topic(ERC721_TRANSFER_TOPIC))
+        .map(|log| (log.address, log.topics[3]))
+        .collect();
+    let locks = [topic(ERC5192_LOCKED_TOPIC), topic(ERC5192_UNLOCKED_TOPIC)];
+    logs.retain(|log| match log.topics.get(0) {
+        Some(event_topic) if locks.contains(event_topic) => {
+            log.data.0.len() == 32 && watched.contains(&(log.address, H256::from_slice(&log.data.0)))
         },
-        _ => Some(err),
+        _ => true,
+    });
+}
+
+/// The topic filters of the tracked events.
+/// With a watchlist, the transfers from and to the watched addresses are fetched by four filters,
+/// one for each position of `_from` and `_to`: the topics 1 and 2 of an ERC721 `Transfer`,
+/// the topics 2 and 3 of the ERC1155 transfers. The ERC5192 `Locked` and `Unlocked` events only
+/// carry the token id, they are fetched without an address filter and only the ones of the watched
+/// transfers are kept, see `retain_watched_locks`. The upgrades are still fetched to keep the contract
+/// cache up to date.
+fn topic_filters(config: &EventsConfig) -> Vec<TopicFilter> {
+    let upgrades = vec![topic(EIP1967_UPGRADED_TOPIC), topic(EIP1967_BEACON_UPGRADED_TOPIC)];
+    let watchlist = match &config.watchlist {
+        Some(watchlist) => watchlist,
+        None => {
+            let mut topics: Vec<H256> = config.standards.iter().flat_map(|standard| standard.topics()).collect();
+            topics.extend(upgrades);
+            return vec![TopicFilter {
+                topic0: Some(topics),
+                ..Default::default()
+            }]
+        }
+    };
+
+    let mut filters = vec![TopicFilter {
+        topic0: Some(upgrades),
+        ..Default::default()
+    }];
+    if config.standards.contains(&Standard::Erc5192) {
+        filters.push(TopicFilter {
+            topic0: Some(Standard::Erc5192.topics()),
+            ..Default::default()
+        });
+    }
+    let erc721 = Some(Standard::Erc721.topics());
+    let erc1155 = Some(Standard::Erc1155.topics());
+    for addresses in watchlist.addresses().chunks(config.max_addresses_per_filter.max(1)) {
+        let watched = Some(addresses.iter().map(|address| H256::from(*address)).collect::<Vec<H256>>());
+        if config.standards.contains(&Standard::Erc721) {
+            filters.push(TopicFilter { topic0: erc721.clone(), topic1: watched.clone(), ..Default::default() });
+            filters.push(TopicFilter { topic0: erc721.clone(), topic2: watched.clone(), ..Default::default() });
+        }
+        if config.standards.contains(&Standard::Erc1155) {
+            filters.push(TopicFilter { topic0: erc1155.clone(), topic2: watched.clone(), ..Default::default() });
+            filters.push(TopicFilter { topic0: erc1155.clone(), topic3: watched, ..Default::default() });
+        }
     }
+    filters
 }
 
-async fn process_log(client: &EvmClient, log: &Log, erc721_transfer_topic: H256, erc1155_transfer_single_topic: H256, result: &mut Vec<Event>) -> Result<()> {
-    Ok(if log.topics[0] == erc721_transfer_topic {
+/// The timestamp of each block is fetched once. The timestamps of the range are kept until its
+/// events are set, the `EvmClient` only caches them for the next ranges.
+async fn set_block_timestamps(client: &EvmClient, events: &mut Vec<Event>, concurrency: usize) -> Result<()> {
+    let blocks: BTreeSet<u64> = events.iter().filter_map(|event| event.block_number()).collect();
+    let results: Vec<Result<(u64, u64)>> = stream::iter(blocks)
+        .map(|block_number| async move { Ok((block_number, client.get_block_timestamp(block_number).await?)) })
+        .buffer_unordered(concurrency)
+        .collect()
+        .await;
+    let timestamps = results.into_iter().collect::<Result<HashMap<u64, u64>>>()?;
 
-        // ERC721
-        if log.topics.len() == 4 &&
-            client.is_erc721(log.address).await? &&
-                client.supports_erc721_metadata(log.address).await? {
-            result.push(build_erc721_event(&log));
+    for event in events.iter_mut() {
+        if let Some(timestamp) = event.block_number().and_then(|block_number| timestamps.get(&block_number)) {
+            event.set_block_timestamp(*timestamp);
         }
+    }
+    Ok(())
+}
 
+fn process_err(log: &Log, err: Error) -> Option<Error> {
+    // a malformed log or a failed call is reported and skipped, retrying gives the same error
+    if err.recovery() == Recovery::Skip {
+        error!("{:?} >>> {:?}", log, err);
+        None
     } else {
+        Some(err)
+    }
+}
 
-        // ERC1155
-        if client.is_erc1155(log.address).await? && 
-            client.supports_erc1155_metadata(log.address).await? {
-            if log.topics[0] == erc1155_transfer_single_topic {
-                let event = build_erc1155_event(&log);
-                result.push(event);
-            } else {
-                let mut events = build_erc1155_events(&log);
-                result.append(&mut events);
-            };
+/// Decode a log, the logs of the denylisted contracts and the upgrades decode into no event
+fn process_log(client: &EvmClient, config: &EventsConfig, log: &Log) -> Result<Vec<Event>> {
+    if config.denylist.contains(&log.address) {
+        return Ok(vec![]);
+    }
+
+    let event_topic = log.topics.get(0);
+    if event_topic == Some(&topic(EIP1967_UPGRADED_TOPIC)) || event_topic == Some(&topic(EIP1967_BEACON_UPGRADED_TOPIC)) {
+        // Proxy upgraded, what is known about the collection may be stale
+        if client.contract_cache().invalidate(&log.address) {
+            info!("{:?} was upgraded, its cached classification and metadata are invalidated", log.address);
         }
+        return Ok(vec![]);
+    }
 
-    })
+    decode_log(log)
+}
+
+/// Decode a log into events, without any call to a node.
+/// The logs which are not NFT events, including the ERC20 `Transfer` logs, decode into no event.
+/// The decoded events are not classified yet: the contracts may not be visual NFTs, the ERC721
+/// transfers are not soulbound and only the transfers to the zero address are burns.
+/// A log with the topic of an NFT event but an invalid layout is a `DecodeError`.
+pub fn decode_log(log: &Log) -> Result<Vec<Event>> {
+    let event_topic = match log.topics.get(0) {
+        Some(event_topic) => *event_topic,
+        None => return Ok(vec![]),
+    };
+
+    if event_topic == topic(ERC721_TRANSFER_TOPIC) {
+        // ERC721, the ERC20 Transfer has the same topic but its value is not indexed
+        if log.topics.len() == 4 {
+            return Ok(vec![build_erc721_event(log)]);
+        }
+    } else if event_topic == topic(ERC5192_LOCKED_TOPIC) || event_topic == topic(ERC5192_UNLOCKED_TOPIC) {
+        return Ok(vec![build_erc5192_event(log)?]);
+    } else if event_topic == topic(ERC1155_TRANSFER_SINGLE_TOPIC) {
+        return Ok(vec![build_erc1155_event(log)?]);
+    } else if event_topic == topic(ERC1155_TRANSFER_BATCH_TOPIC) {
+        return build_erc1155_events(log);
+    }
+
+    Ok(vec![])
 }
 
 fn build_erc721_event(log: &Log) -> Event {
@@ -145,109 +515,528 @@ fn build_erc721_event(log: &Log) -> Event {
     Event::Erc721(
         Erc721Event {
             block_number: log.block_number.map(|b| b.as_u64()),
+            block_hash: log.block_hash,
+            block_timestamp: None,
             address: log.address,
             transaction_hash: log.transaction_hash,
+            transaction_index: log.transaction_index.map(|i| i.as_u64()),
+            log_index: log.log_index.map(|i| i.as_u64()),
+            removed: log.removed.unwrap_or(false),
             from,
             to,
             token_id,
+            kind: TransferKind::classify(&from, &to, &[]),
+            soulbound: false,
         }
     )
 }
 
-fn build_erc1155_event(log: &Log) -> Event {
+fn build_erc5192_event(log: &Log) -> Result<Event> {
+    if log.data.0.len() != 32 {
+        return Err(decode_error(log, "Locked and Unlocked must have 32 bytes of data"));
+    }
+    Ok(Event::Erc5192(
+        Erc5192Event {
+            block_number: log.block_number.map(|b| b.as_u64()),
+            block_hash: log.block_hash,
+            block_timestamp: None,
+            address: log.address,
+            transaction_hash: log.transaction_hash,
+            transaction_index: log.transaction_index.map(|i| i.as_u64()),
+            log_index: log.log_index.map(|i| i.as_u64()),
+            removed: log.removed.unwrap_or(false),
+            token_id: U256::from_big_endian(&log.data.0[0..32]),
+            locked: log.topics[0] == topic(ERC5192_LOCKED_TOPIC),
+        }
+    ))
+}
+
+fn build_erc1155_event(log: &Log) -> Result<Event> {
+    if log.topics.len() != 4 || log.data.0.len() != 64 {
+        return Err(decode_error(log, "TransferSingle must have 4 topics and 64 bytes of data"));
+    }
     let token_id = U256::from_big_endian(&log.data.0[0..32]);
     let amount = U256::from_big_endian(&log.data.0[32..64]);
     let block_number = log.block_number.map(|b| b.as_u64());
+    let block_hash = log.block_hash;
     let address = log.address;
     let transaction_hash = log.transaction_hash;
+    let transaction_index = log.transaction_index.map(|i| i.as_u64());
+    let log_index = log.log_index.map(|i| i.as_u64());
+    let removed = log.removed.unwrap_or(false);
     let operator = H160::from(log.topics[1]);
     let from = H160::from(log.topics[2]);
     let to = H160::from(log.topics[3]);
 
-    Event::Erc1155(
+    Ok(Event::Erc1155(
         Erc1155Event {
             block_number,
+            block_hash,
+            block_timestamp: None,
             address,
             transaction_hash,
+            transaction_index,
+            log_index,
+            removed,
             operator,
             from,
             to,
             token_id, 
             amount,
+            kind: TransferKind::classify(&from, &to, &[]),
+            batch_index: 0,
         }
-    )
+    ))
 }
 
-fn build_erc1155_events(log: &Log) -> Vec<Event> {
+fn build_erc1155_events(log: &Log) -> Result<Vec<Event>> {
+    let (token_ids, amounts) = get_ids_and_amounts(log)?;
+
     let block_number = log.block_number.map(|b| b.as_u64());
+    let block_hash = log.block_hash;
     let address = log.address;
     let transaction_hash = log.transaction_hash;
+    let transaction_index = log.transaction_index.map(|i| i.as_u64());
+    let log_index = log.log_index.map(|i| i.as_u64());
+    let removed = log.removed.unwrap_or(false);
     let operator = H160::from(log.topics[1]);
     let from = H160::from(log.topics[2]);
     let to = H160::from(log.topics[3]);
 
-    // owner_list: token_id_list
-    // from: 1
-    // from: 2
-    // from: 3
-    // to: 1
-    // to: 2
-    // to: 3
-    let (token_ids, amounts) = get_ids_and_amounts(&log.data);
-    let mut owner_list = vec![];
-    let mut token_id_list = vec![];
-    for i in 0..token_ids.len() {
-        let token_id = token_ids[i];
-        owner_list.push(from);
-        token_id_list.push(token_id);
-    }
-    for i in 0..token_ids.len() {
-        let token_id = token_ids[i];
-        owner_list.push(to);
-        token_id_list.push(token_id);
-    }
-
-    //
     let mut events = vec![];
-    for i in 0..token_ids.len() {
-        let token_id = token_ids[i];
-        let amount = amounts[i];
+    for (i, (token_id, amount)) in token_ids.into_iter().zip(amounts).enumerate() {
         let event = Event::Erc1155(
             Erc1155Event {
                 block_number,
+                block_hash,
+                block_timestamp: None,
                 address,
                 transaction_hash,
+                transaction_index,
+                log_index,
+                removed,
                 operator,
                 from,
                 to,
                 token_id, 
                 amount,
+                kind: TransferKind::classify(&from, &to, &[]),
+                batch_index: i as u64,
             }
         );
         events.push(event);
     }
 
-    events
+    Ok(events)
+}
+
+/// `TransferBatch(address indexed _operator, address indexed _from, address indexed _to, uint256[] _ids, uint256[] _values)`
+fn transfer_batch_event() -> ethabi::Event {
+    let param = |name: &str, kind: ParamType, indexed: bool| EventParam {
+        name: name.to_owned(),
+        kind,
+        indexed,
+    };
+    let uints = || ParamType::Array(Box::new(ParamType::Uint(256)));
+    ethabi::Event {
+        name: "TransferBatch".to_owned(),
+        inputs: vec![
+            param("_operator", ParamType::Address, true),
+            param("_from", ParamType::Address, true),
+            param("_to", ParamType::Address, true),
+            param("_ids", uints(), false),
+            param("_values", uints(), false),
+        ],
+        anonymous: false,
+    }
+}
+
+/// Decode the ids and the values of a `TransferBatch` log against the event signature.
+/// The two arrays must have the same length.
+fn get_ids_and_amounts(log: &Log) -> Result<(Vec<U256>, Vec<U256>)> {
+    let raw_log = RawLog {
+        topics: log.topics.clone(),
+        data: log.data.0.clone(),
+    };
+    let mut params = transfer_batch_event()
+        .parse_log(raw_log)
+        .map_err(|err| decode_error(log, &format!("invalid TransferBatch, {}", err)))?
+        .params;
+
+    let amounts = to_uints(log, params.pop().map(|p| p.value))?;
+    let ids = to_uints(log, params.pop().map(|p| p.value))?;
+    if ids.len() != amounts.len() {
+        return Err(decode_error(log, &format!("TransferBatch has {} ids but {} values", ids.len(), amounts.len())));
+    }
+
+    Ok((ids, amounts))
+}
+
+fn to_uints(log: &Log, token: Option<Token>) -> Result<Vec<U256>> {
+    match token {
+        Some(Token::Array(tokens)) => tokens
+            .into_iter()
+            .map(|token| token.into_uint().ok_or_else(|| decode_error(log, "uint256 expected")))
+            .collect(),
+        _ => Err(decode_error(log, "uint256[] expected")),
+    }
+}
+
+fn decode_error(log: &Log, reason: &str) -> Error {
+    Error::DecodeError {
+        address: log.address,
+        block_number: log.block_number.map(|n| n.as_u64()),
+        transaction_hash: log.transaction_hash,
+        log_index: log.log_index.map(|i| i.as_u64()),
+        reason: reason.to_owned(),
+    }
 }
 
-fn get_ids_and_amounts(data: &Bytes) -> (Vec<U256>, Vec<U256>) {
-    let mut items = vec![];
-    for i in 0..data.0.len() / 32 {
-        if i >= 2 {
-            let item = &data.0[32 * i..32 * i + 32];
-            items.push(item);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use web3::{signing::keccak256, types::Bytes};
+
+    fn log_with(topics: Vec<H256>, data: Vec<u8>) -> Log {
+        Log {
+            address: H160::from_low_u64_be(1),
+            topics,
+            data: Bytes(data),
+            block_hash: Some(H256::from_low_u64_be(3)),
+            block_number: Some(100.into()),
+            transaction_hash: Some(H256::from_low_u64_be(2)),
+            transaction_index: Some(4.into()),
+            log_index: Some(5.into()),
+            transaction_log_index: None,
+            log_type: None,
+            removed: None,
         }
     }
 
-    if items.len() > 0 && items.len() % 2 == 0 {
-        let ids = &items[0..items.len() / 2][1..];
-        let ids: Vec<U256> = ids.iter().map(|id| U256::from_big_endian(id)).collect();
+    #[test]
+    fn test_event_topics() {
+        assert_eq!(topic(ERC721_TRANSFER_TOPIC), H256::from(keccak256(b"Transfer(address,address,uint256)")));
+        assert_eq!(topic(ERC1155_TRANSFER_SINGLE_TOPIC), H256::from(keccak256(b"TransferSingle(address,address,address,uint256,uint256)")));
+        assert_eq!(topic(ERC1155_TRANSFER_BATCH_TOPIC), H256::from(keccak256(b"TransferBatch(address,address,address,uint256[],uint256[])")));
+        assert_eq!(topic(ERC5192_LOCKED_TOPIC), H256::from(keccak256(b"Locked(uint256)")));
+        assert_eq!(topic(ERC5192_UNLOCKED_TOPIC), H256::from(keccak256(b"Unlocked(uint256)")));
+        assert_eq!(topic(EIP1967_UPGRADED_TOPIC), H256::from(keccak256(b"Upgraded(address)")));
+        assert_eq!(topic(EIP1967_BEACON_UPGRADED_TOPIC), H256::from(keccak256(b"BeaconUpgraded(address)")));
+    }
+
+    #[test]
+    fn test_decode_erc721_log() {
+        let from = H160::from_low_u64_be(10);
+        let to = H160::from_low_u64_be(11);
+        let topics = vec![topic(ERC721_TRANSFER_TOPIC), from.into(), to.into(), H256::from_low_u64_be(1234)];
+        let log = log_with(topics, vec![]);
+        let mut events = decode_log(&log).unwrap();
+        assert_eq!(1, events.len());
+        match events.remove(0) {
+            Event::Erc721(event) => {
+                assert_eq!(Some(100), event.block_number);
+                assert_eq!(Some(H256::from_low_u64_be(3)), event.block_hash);
+                assert_eq!(None, event.block_timestamp);
+                assert_eq!(Some(4), event.transaction_index);
+                assert_eq!(Some(5), event.log_index);
+                assert_eq!(false, event.removed);
+                assert_eq!(from, event.from);
+                assert_eq!(to, event.to);
+                assert_eq!(U256::from(1234), event.token_id);
+                assert_eq!(TransferKind::Transfer, event.kind);
+                assert_eq!(false, event.soulbound);
+            },
+            _ => panic!("not an erc721 event"),
+        }
+    }
 
-        let amounts = &items[items.len() / 2..items.len()][1..];
-        let amounts: Vec<U256> = amounts.iter().map(|id| U256::from_big_endian(id)).collect();
+    #[test]
+    fn test_decode_other_logs() {
+        // ERC20 Transfer, the value is in the data
+        let topics = vec![topic(ERC721_TRANSFER_TOPIC), H256::from_low_u64_be(10), H256::from_low_u64_be(11)];
+        assert!(decode_log(&log_with(topics, vec![0u8; 32])).unwrap().is_empty());
 
-        (ids, amounts)
-    } else {
-        (vec![], vec![])
+        assert!(decode_log(&log_with(vec![topic(EIP1967_UPGRADED_TOPIC)], vec![])).unwrap().is_empty());
+        assert!(decode_log(&log_with(vec![], vec![])).unwrap().is_empty());
+
+        let log = log_with(vec![topic(ERC5192_LOCKED_TOPIC)], vec![0u8; 31]);
+        assert!(matches!(decode_log(&log), Err(Error::DecodeError { .. })));
+    }
+
+    #[test]
+    fn test_transfer_kind() {
+        let zero = H160::zero();
+        let alice = H160::from_low_u64_be(10);
+        let bob = H160::from_low_u64_be(11);
+        let dead = H160::from_str(DEAD_ADDRESS).unwrap();
+        let burn_addresses = EventsConfig::default().burn_addresses;
+
+        assert_eq!(TransferKind::Mint, TransferKind::classify(&zero, &alice, &burn_addresses));
+        assert_eq!(TransferKind::Burn, TransferKind::classify(&alice, &zero, &burn_addresses));
+        assert_eq!(TransferKind::Burn, TransferKind::classify(&alice, &dead, &burn_addresses));
+        assert_eq!(TransferKind::Transfer, TransferKind::classify(&alice, &dead, &[]));
+        assert_eq!(TransferKind::Transfer, TransferKind::classify(&alice, &bob, &burn_addresses));
+        assert_eq!(TransferKind::SelfTransfer, TransferKind::classify(&alice, &alice, &burn_addresses));
+    }
+
+    #[test]
+    fn test_build_erc5192_event() {
+        let mut data = [0u8; 32];
+        U256::from(1234).to_big_endian(&mut data);
+
+        let log = log_with(vec![topic(ERC5192_LOCKED_TOPIC)], data.to_vec());
+        match build_erc5192_event(&log).unwrap() {
+            Event::Erc5192(event) => {
+                assert_eq!(U256::from(1234), event.token_id);
+                assert_eq!(true, event.locked);
+            },
+            _ => panic!("not an erc5192 event"),
+        }
+
+        let log = log_with(vec![topic(ERC5192_UNLOCKED_TOPIC)], data.to_vec());
+        match build_erc5192_event(&log).unwrap() {
+            Event::Erc5192(event) => assert_eq!(false, event.locked),
+            _ => panic!("not an erc5192 event"),
+        }
+    }
+
+    fn transfer_batch_log(ids: &[U256], amounts: &[U256]) -> Log {
+        let topics = vec![
+            topic(ERC1155_TRANSFER_BATCH_TOPIC),
+            H160::from_low_u64_be(9).into(),
+            H160::from_low_u64_be(10).into(),
+            H160::from_low_u64_be(11).into(),
+        ];
+        let data = ethabi::encode(&[
+            Token::Array(ids.iter().map(|id| Token::Uint(*id)).collect()),
+            Token::Array(amounts.iter().map(|amount| Token::Uint(*amount)).collect()),
+        ]);
+        log_with(topics, data)
+    }
+
+    #[test]
+    fn test_build_erc1155_events() {
+        let ids = vec![U256::from(1), U256::MAX];
+        let amounts = vec![U256::from(10), U256::from(20)];
+        let events = build_erc1155_events(&transfer_batch_log(&ids, &amounts)).unwrap();
+        assert_eq!(2, events.len());
+        match &events[1] {
+            Event::Erc1155(event) => {
+                assert_eq!(H160::from_low_u64_be(9), event.operator);
+                assert_eq!(H160::from_low_u64_be(10), event.from);
+                assert_eq!(H160::from_low_u64_be(11), event.to);
+                assert_eq!(U256::MAX, event.token_id);
+                assert_eq!(U256::from(20), event.amount);
+                assert_eq!(1, event.batch_index);
+            },
+            _ => panic!("not an erc1155 event"),
+        }
+    }
+
+    #[test]
+    fn test_malformed_transfer_logs() {
+        // the lengths of ids and values differ
+        let log = transfer_batch_log(&[U256::from(1), U256::from(2)], &[U256::from(10)]);
+        assert!(matches!(build_erc1155_events(&log), Err(Error::DecodeError { .. })));
+
+        // the data is truncated
+        let mut log = transfer_batch_log(&[U256::from(1)], &[U256::from(10)]);
+        log.data.0.truncate(100);
+        assert!(matches!(build_erc1155_events(&log), Err(Error::DecodeError { .. })));
+
+        // a topic is missing
+        let mut log = transfer_batch_log(&[U256::from(1)], &[U256::from(10)]);
+        log.topics.pop();
+        assert!(matches!(build_erc1155_events(&log), Err(Error::DecodeError { .. })));
+
+        let log = log_with(vec![topic(ERC1155_TRANSFER_SINGLE_TOPIC)], vec![0u8; 64]);
+        assert!(matches!(build_erc1155_event(&log), Err(Error::DecodeError { .. })));
+    }
+
+    #[test]
+    fn test_topic_filters() {
+        let config = EventsConfig::default();
+        let filters = topic_filters(&config);
+        assert_eq!(1, filters.len());
+        assert_eq!(7, filters[0].topic0.as_ref().unwrap().len());
+        assert_eq!(None, filters[0].topic1);
+
+        // the watched addresses are split in chunks, 4 filters for each chunk
+        let alice = H160::from_low_u64_be(10);
+        let bob = H160::from_low_u64_be(11);
+        let config = EventsConfig {
+            watchlist: Some(Watchlist::new(vec![alice, bob])),
+            max_addresses_per_filter: 1,
+            ..Default::default()
+        };
+        let filters = topic_filters(&config);
+        assert_eq!(10, filters.len());
+        assert_eq!(None, filters[0].topic1);
+        // the ERC5192 events are not filtered by address
+        assert_eq!(Some(Standard::Erc5192.topics()), filters[1].topic0);
+        assert_eq!(None, filters[1].topic1);
+        assert_eq!(Some(vec![topic(ERC721_TRANSFER_TOPIC)]), filters[2].topic0);
+        assert_eq!(Some(vec![H256::from(alice)]), filters[2].topic1);
+        assert_eq!(Some(vec![H256::from(alice)]), filters[3].topic2);
+        assert_eq!(Some(vec![H256::from(alice)]), filters[4].topic2);
+        assert_eq!(None, filters[4].topic1);
+        assert_eq!(Some(vec![H256::from(alice)]), filters[5].topic3);
+        assert_eq!(Some(vec![H256::from(bob)]), filters[6].topic1);
+
+        // the watchlist is updated at runtime
+        config.watchlist.as_ref().unwrap().remove(&bob);
+        assert_eq!(6, topic_filters(&config).len());
+
+        // only the topics of the tracked standards
+        let config = EventsConfig {
+            standards: vec![Standard::Erc1155].into_iter().collect(),
+            ..Default::default()
+        };
+        let filters = topic_filters(&config);
+        assert_eq!(4, filters[0].topic0.as_ref().unwrap().len());
+        assert!(!filters[0].topic0.as_ref().unwrap().contains(&topic(ERC721_TRANSFER_TOPIC)));
+    }
+
+    #[test]
+    fn test_retain_watched_locks() {
+        let alice = H160::from_low_u64_be(10);
+        let token = |id: u64| H256::from_low_u64_be(id);
+        let transfer = log_with(vec![topic(ERC721_TRANSFER_TOPIC), H256::zero(), H256::from(alice), token(7)], vec![]);
+        let lock = |id: u64| log_with(vec![topic(ERC5192_LOCKED_TOPIC)], token(id).as_bytes().to_vec());
+        let mut other_contract = lock(7);
+        other_contract.address = H160::from_low_u64_be(2);
+        let logs = vec![transfer, lock(7), lock(8), other_contract];
+
+        // without a watchlist, every lock is kept
+        let mut kept = logs.clone();
+        retain_watched_locks(&EventsConfig::default(), &mut kept);
+        assert_eq!(logs, kept);
+
+        // only the locks of the watched transfers
+        let config = EventsConfig {
+            watchlist: Some(Watchlist::new(vec![alice])),
+            ..Default::default()
+        };
+        let mut kept = logs.clone();
+        retain_watched_locks(&config, &mut kept);
+        assert_eq!(logs[..2].to_vec(), kept);
+    }
+
+    #[tokio::test]
+    async fn test_set_block_timestamps() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+        use web3::{transports::Http, Web3};
+
+        // more blocks than the client caches
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let url = crate::rpc_stub::serve(move |method, params| match method {
+            "eth_getBlockByNumber" => {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let number = u64::from_str_radix(params[0].as_str().unwrap().trim_start_matches("0x"), 16).unwrap();
+                Ok(crate::rpc_stub::block(number, 1_000_000 + number))
+            },
+            _ => Err(jsonrpc_core::Error::method_not_found()),
+        })
+        .await;
+        let client = EvmClient::new("Test".to_owned(), Web3::new(Http::new(&url).unwrap()));
+        let mut events: Vec<Event> = (0..1100)
+            .map(|block_number| {
+                Event::Erc721(Erc721Event {
+                    block_number: Some(block_number),
+                    block_hash: None,
+                    block_timestamp: None,
+                    address: H160::from_low_u64_be(1),
+                    transaction_hash: None,
+                    transaction_index: None,
+                    log_index: Some(0),
+                    removed: false,
+                    from: H160::zero(),
+                    to: H160::from_low_u64_be(2),
+                    token_id: U256::from(block_number),
+                    kind: TransferKind::Mint,
+                    soulbound: false,
+                })
+            })
+            .collect();
+
+        set_block_timestamps(&client, &mut events, 16).await.unwrap();
+        // each block is fetched once
+        assert_eq!(1100, requests.load(Ordering::SeqCst));
+        for event in &events {
+            match event {
+                Event::Erc721(e) => assert_eq!(Some(1_000_000 + e.block_number.unwrap()), e.block_timestamp),
+                _ => panic!("not an erc721 event"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge_logs() {
+        let log = |block: u64, index: Option<u64>, hash: Option<u64>| Log {
+            block_number: Some(block.into()),
+            block_hash: hash.map(|_| H256::from_low_u64_be(block)),
+            transaction_hash: hash.map(H256::from_low_u64_be),
+            log_index: index.map(U256::from),
+            ..log_with(vec![], vec![])
+        };
+        let mut logs = vec![
+            log(2, Some(0), Some(1)),
+            log(1, Some(3), Some(1)),
+            log(2, Some(0), Some(1)),
+            // pending logs have no index nor hashes, they are not the same log
+            log(3, None, None),
+            log(3, None, None),
+        ];
+        merge_logs(&mut logs);
+        let keys: Vec<(u64, Option<U256>)> = logs.iter().map(|log| (log.block_number.unwrap().as_u64(), log.log_index)).collect();
+        assert_eq!(vec![(1, Some(U256::from(3))), (2, Some(U256::zero())), (3, None), (3, None)], keys);
+    }
+
+    fn arb_u256() -> impl Strategy<Value = U256> {
+        any::<[u8; 32]>().prop_map(|bytes| U256::from_big_endian(&bytes))
+    }
+
+    proptest! {
+        #[test]
+        fn prop_transfer_batch_round_trip(pairs in prop::collection::vec((arb_u256(), arb_u256()), 0..64)) {
+            let (ids, amounts): (Vec<U256>, Vec<U256>) = pairs.into_iter().unzip();
+            let events = build_erc1155_events(&transfer_batch_log(&ids, &amounts)).unwrap();
+            prop_assert_eq!(ids.len(), events.len());
+            for (i, event) in events.into_iter().enumerate() {
+                match event {
+                    Event::Erc1155(event) => {
+                        prop_assert_eq!(ids[i], event.token_id);
+                        prop_assert_eq!(amounts[i], event.amount);
+                        prop_assert_eq!(i as u64, event.batch_index);
+                    },
+                    _ => prop_assert!(false, "not an erc1155 event"),
+                }
+            }
+        }
+
+        #[test]
+        fn prop_transfer_batch_length_mismatch(
+            ids in prop::collection::vec(arb_u256(), 0..32),
+            amounts in prop::collection::vec(arb_u256(), 0..32),
+        ) {
+            prop_assume!(ids.len() != amounts.len());
+            let result = build_erc1155_events(&transfer_batch_log(&ids, &amounts));
+            prop_assert!(matches!(result, Err(Error::DecodeError { .. })), "a decode error was expected");
+        }
+
+        #[test]
+        fn prop_transfer_batch_arbitrary_data(data in prop::collection::vec(any::<u8>(), 0..512)) {
+            // never panics, anything that is not a valid batch is a decode error
+            let mut log = transfer_batch_log(&[], &[]);
+            log.data = Bytes(data);
+            if let Err(err) = build_erc1155_events(&log) {
+                prop_assert!(matches!(err, Error::DecodeError { .. }), "a decode error was expected");
+            }
+        }
     }
 }
@@ -0,0 +1,243 @@
+//! This module contains the bytecode based capability fingerprinting of NFT contracts.
+//! ERC-165 answers can be missing or wrong, so the function selectors are also looked up
+//! in the bytecode. The solidity dispatcher compares the selector of a call with `PUSH4`
+//! constants, the selectors are collected from these instructions.
+use std::collections::BTreeSet;
+use web3::{
+    signing::keccak256,
+    types::{H160, H256},
+};
+
+/// A capability detected in the bytecode of a contract
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ContractFeature {
+    /// `ownerOf`, `balanceOf` and `safeTransferFrom` of ERC721
+    Erc721,
+    /// `tokenURI` of the ERC721 metadata extension
+    Erc721Metadata,
+    /// `totalSupply`, `tokenByIndex` and `tokenOfOwnerByIndex` of the ERC721 enumeration extension
+    Erc721Enumerable,
+    /// The custom errors of the ERC721A implementation
+    Erc721A,
+    /// `balanceOfBatch` and `safeBatchTransferFrom` of ERC1155
+    Erc1155,
+    /// `uri` of the ERC1155 metadata URI extension
+    Erc1155MetadataUri,
+    /// `burn` of ERC721 or ERC1155
+    Burnable,
+    /// `owner` and `transferOwnership`
+    Ownable,
+    /// `royaltyInfo` of ERC2981
+    Erc2981,
+    /// The interface id or the `MetadataUpdate`/`BatchMetadataUpdate` events of ERC4906
+    Erc4906,
+}
+
+/// The fingerprint of a contract
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContractFingerprint {
+    /// The keccak256 hash of the analysed bytecode, clones of a collection share the same hash
+    pub code_hash: H256,
+    /// The implementation whose bytecode was analysed if the contract is a proxy
+    pub implementation: Option<H160>,
+    /// The selectors found in the bytecode
+    pub selectors: BTreeSet<[u8; 4]>,
+    /// The detected features
+    pub features: BTreeSet<ContractFeature>,
+}
+
+// ERC721
+const OWNER_OF: [u8; 4] = [0x63, 0x52, 0x21, 0x1e]; // ownerOf(uint256)
+const BALANCE_OF: [u8; 4] = [0x70, 0xa0, 0x82, 0x31]; // balanceOf(address)
+const SAFE_TRANSFER_FROM: [u8; 4] = [0x42, 0x84, 0x2e, 0x0e]; // safeTransferFrom(address,address,uint256)
+const TOKEN_URI: [u8; 4] = [0xc8, 0x7b, 0x56, 0xdd]; // tokenURI(uint256)
+const TOTAL_SUPPLY: [u8; 4] = [0x18, 0x16, 0x0d, 0xdd]; // totalSupply()
+const TOKEN_BY_INDEX: [u8; 4] = [0x4f, 0x6c, 0xce, 0xe7]; // tokenByIndex(uint256)
+const TOKEN_OF_OWNER_BY_INDEX: [u8; 4] = [0x2f, 0x74, 0x5c, 0x59]; // tokenOfOwnerByIndex(address,uint256)
+const BURN: [u8; 4] = [0x42, 0x96, 0x6c, 0x68]; // burn(uint256)
+
+// ERC721A custom errors
+const ERC721A_ERRORS: [[u8; 4]; 6] = [
+    [0xdf, 0x2d, 0x9b, 0x42], // OwnerQueryForNonexistentToken()
+    [0xa1, 0x14, 0x81, 0x00], // TransferFromIncorrectOwner()
+    [0xd1, 0xa5, 0x7e, 0xd6], // TransferToNonERC721ReceiverImplementer()
+    [0xb5, 0x62, 0xe8, 0xdd], // MintZeroQuantity()
+    [0x2e, 0x07, 0x63, 0x00], // MintToZeroAddress()
+    [0x8f, 0x4e, 0xb6, 0x04], // BalanceQueryForZeroAddress()
+];
+
+// ERC1155
+const BALANCE_OF_BATCH: [u8; 4] = [0x4e, 0x12, 0x73, 0xf4]; // balanceOfBatch(address[],uint256[])
+const SAFE_BATCH_TRANSFER_FROM: [u8; 4] = [0x2e, 0xb2, 0xc2, 0xd6]; // safeBatchTransferFrom(address,address,uint256[],uint256[],bytes)
+const URI: [u8; 4] = [0x0e, 0x89, 0x34, 0x1c]; // uri(uint256)
+const BURN_ERC1155: [u8; 4] = [0xf5, 0x29, 0x8a, 0xca]; // burn(address,uint256,uint256)
+
+// Others
+const OWNER: [u8; 4] = [0x8d, 0xa5, 0xcb, 0x5b]; // owner()
+const TRANSFER_OWNERSHIP: [u8; 4] = [0xf2, 0xfd, 0xe3, 0x8b]; // transferOwnership(address)
+const ROYALTY_INFO: [u8; 4] = [0x2a, 0x55, 0x20, 0x5a]; // royaltyInfo(uint256,uint256)
+const ERC4906_INTERFACE_ID: [u8; 4] = [0x49, 0x06, 0x49, 0x06];
+const ERC4906_METADATA_UPDATE_TOPIC: [u8; 32] = [
+    0xf8, 0xe1, 0xa1, 0x5a, 0xba, 0x93, 0x98, 0xe0, 0x19, 0xf0, 0xb4, 0x9d, 0xf1, 0xa4, 0xfd, 0xe9,
+    0x8e, 0xe1, 0x7a, 0xe3, 0x45, 0xcb, 0x5f, 0x6b, 0x5e, 0x2c, 0x27, 0xf5, 0x03, 0x3e, 0x8c, 0xe7,
+]; // MetadataUpdate(uint256)
+const ERC4906_BATCH_METADATA_UPDATE_TOPIC: [u8; 32] = [
+    0x6b, 0xd5, 0xc9, 0x50, 0xa8, 0xd8, 0xdf, 0x17, 0xf7, 0x72, 0xf5, 0xaf, 0x37, 0xcb, 0x36, 0x55,
+    0x73, 0x78, 0x99, 0xcb, 0xf9, 0x03, 0x26, 0x4b, 0x97, 0x95, 0x59, 0x2d, 0xa4, 0x39, 0x66, 0x1c,
+]; // BatchMetadataUpdate(uint256,uint256)
+
+const PUSH1: u8 = 0x60;
+const PUSH4: u8 = 0x63;
+const PUSH32: u8 = 0x7f;
+
+impl ContractFingerprint {
+    /// Analyse the runtime bytecode of a contract
+    pub fn from_code(code: &[u8]) -> ContractFingerprint {
+        let mut selectors = BTreeSet::new();
+        let mut words = BTreeSet::new();
+
+        let mut i = 0;
+        while i < code.len() {
+            let opcode = code[i];
+            if (PUSH1..=PUSH32).contains(&opcode) {
+                let size = (opcode - PUSH1 + 1) as usize;
+                if i + size < code.len() {
+                    let data = &code[i + 1..i + 1 + size];
+                    if opcode == PUSH4 {
+                        selectors.insert([data[0], data[1], data[2], data[3]]);
+                    } else if opcode == PUSH32 {
+                        words.insert(H256::from_slice(data));
+                    }
+                }
+                i += size;
+            }
+            i += 1;
+        }
+
+        let has = |selector: &[u8; 4]| selectors.contains(selector);
+        let mut features = BTreeSet::new();
+        if has(&OWNER_OF) && has(&BALANCE_OF) && has(&SAFE_TRANSFER_FROM) {
+            features.insert(ContractFeature::Erc721);
+        }
+        if has(&TOKEN_URI) {
+            features.insert(ContractFeature::Erc721Metadata);
+        }
+        if has(&TOTAL_SUPPLY) && has(&TOKEN_BY_INDEX) && has(&TOKEN_OF_OWNER_BY_INDEX) {
+            features.insert(ContractFeature::Erc721Enumerable);
+        }
+        if ERC721A_ERRORS.iter().filter(|error| has(error)).count() >= 2 {
+            features.insert(ContractFeature::Erc721A);
+        }
+        if has(&BALANCE_OF_BATCH) && has(&SAFE_BATCH_TRANSFER_FROM) {
+            features.insert(ContractFeature::Erc1155);
+        }
+        if has(&URI) {
+            features.insert(ContractFeature::Erc1155MetadataUri);
+        }
+        if has(&BURN) || has(&BURN_ERC1155) {
+            features.insert(ContractFeature::Burnable);
+        }
+        if has(&OWNER) && has(&TRANSFER_OWNERSHIP) {
+            features.insert(ContractFeature::Ownable);
+        }
+        if has(&ROYALTY_INFO) {
+            features.insert(ContractFeature::Erc2981);
+        }
+        if has(&ERC4906_INTERFACE_ID)
+            || words.contains(&H256::from(ERC4906_METADATA_UPDATE_TOPIC))
+            || words.contains(&H256::from(ERC4906_BATCH_METADATA_UPDATE_TOPIC))
+        {
+            features.insert(ContractFeature::Erc4906);
+        }
+
+        ContractFingerprint {
+            code_hash: H256::from(keccak256(code)),
+            implementation: None,
+            selectors,
+            features,
+        }
+    }
+
+    /// Check if a feature was detected
+    pub fn has(&self, feature: ContractFeature) -> bool {
+        self.features.contains(&feature)
+    }
+}
+
+/// Get the target of an EIP-1167 minimal proxy, which is how most clone collections are deployed
+pub fn minimal_proxy_target(code: &[u8]) -> Option<H160> {
+    const PREFIX: [u8; 10] = [0x36, 0x3d, 0x3d, 0x37, 0x3d, 0x3d, 0x3d, 0x36, 0x3d, 0x73];
+    const SUFFIX: [u8; 15] = [0x5a, 0xf4, 0x3d, 0x82, 0x80, 0x3e, 0x90, 0x3d, 0x91, 0x60, 0x2b, 0x57, 0xfd, 0x5b, 0xf3];
+
+    if code.len() == PREFIX.len() + 20 + SUFFIX.len() && code.starts_with(&PREFIX) && code.ends_with(&SUFFIX) {
+        Some(H160::from_slice(&code[PREFIX.len()..PREFIX.len() + 20]))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push4(selectors: &[[u8; 4]]) -> Vec<u8> {
+        let mut code = vec![];
+        for selector in selectors {
+            code.push(PUSH4);
+            code.extend_from_slice(selector);
+        }
+        code
+    }
+
+    #[test]
+    fn test_selectors() {
+        assert_eq!(&keccak256(b"ownerOf(uint256)")[0..4], &OWNER_OF);
+        assert_eq!(&keccak256(b"tokenOfOwnerByIndex(address,uint256)")[0..4], &TOKEN_OF_OWNER_BY_INDEX);
+        assert_eq!(&keccak256(b"royaltyInfo(uint256,uint256)")[0..4], &ROYALTY_INFO);
+        assert_eq!(&keccak256(b"OwnerQueryForNonexistentToken()")[0..4], &ERC721A_ERRORS[0]);
+        assert_eq!(keccak256(b"MetadataUpdate(uint256)"), ERC4906_METADATA_UPDATE_TOPIC);
+        assert_eq!(keccak256(b"BatchMetadataUpdate(uint256,uint256)"), ERC4906_BATCH_METADATA_UPDATE_TOPIC);
+    }
+
+    #[test]
+    fn test_features() {
+        let code = push4(&[OWNER_OF, BALANCE_OF, SAFE_TRANSFER_FROM, TOKEN_URI, TOTAL_SUPPLY, TOKEN_BY_INDEX, TOKEN_OF_OWNER_BY_INDEX, OWNER, TRANSFER_OWNERSHIP]);
+        let fingerprint = ContractFingerprint::from_code(&code);
+        assert!(fingerprint.has(ContractFeature::Erc721));
+        assert!(fingerprint.has(ContractFeature::Erc721Metadata));
+        assert!(fingerprint.has(ContractFeature::Erc721Enumerable));
+        assert!(fingerprint.has(ContractFeature::Ownable));
+        assert!(!fingerprint.has(ContractFeature::Erc1155));
+        assert!(!fingerprint.has(ContractFeature::Erc2981));
+        assert_eq!(H256::from(keccak256(&code)), fingerprint.code_hash);
+
+        let mut code = push4(&[ERC721A_ERRORS[0], ERC721A_ERRORS[1], ROYALTY_INFO]);
+        code.push(PUSH32);
+        code.extend_from_slice(&ERC4906_METADATA_UPDATE_TOPIC);
+        let fingerprint = ContractFingerprint::from_code(&code);
+        assert!(fingerprint.has(ContractFeature::Erc721A));
+        assert!(fingerprint.has(ContractFeature::Erc2981));
+        assert!(fingerprint.has(ContractFeature::Erc4906));
+    }
+
+    #[test]
+    fn test_push_data_is_skipped() {
+        // the selector is the data of a PUSH5, not a PUSH4 instruction
+        let code = vec![0x64, PUSH4, 0x63, 0x52, 0x21, 0x1e];
+        assert!(ContractFingerprint::from_code(&code).selectors.is_empty());
+
+        // truncated PUSH4 at the end of the code
+        let code = vec![PUSH4, 0x63, 0x52];
+        assert!(ContractFingerprint::from_code(&code).selectors.is_empty());
+    }
+
+    #[test]
+    fn test_minimal_proxy_target() {
+        let target = H160::from_low_u64_be(0x1234);
+        let mut code = vec![0x36, 0x3d, 0x3d, 0x37, 0x3d, 0x3d, 0x3d, 0x36, 0x3d, 0x73];
+        code.extend_from_slice(target.as_bytes());
+        code.extend_from_slice(&[0x5a, 0xf4, 0x3d, 0x82, 0x80, 0x3e, 0x90, 0x3d, 0x91, 0x60, 0x2b, 0x57, 0xfd, 0x5b, 0xf3]);
+        assert_eq!(Some(target), minimal_proxy_target(&code));
+        assert_eq!(None, minimal_proxy_target(&code[1..]));
+    }
+}
@@ -0,0 +1,220 @@
+//! This module contains the HTTP transport of the RPC endpoints.
+//! Unlike the HTTP transport of web3, it reads the status and the headers of the responses: a request
+//! refused with a `429` status is returned as a rate limited RPC error, with the delay asked by its
+//! `Retry-After` header, see `error::rate_limit`. The JSON-RPC error in the body of another refused request
+//! is returned as such, the status is returned as an IO error only if the body is not a JSON-RPC answer.
+use futures::future::{BoxFuture, FutureExt};
+use jsonrpc_core::{Call, ErrorCode, Output, Request, Response, Value};
+use reqwest::{header::RETRY_AFTER, Client, StatusCode, Url};
+use std::{
+    collections::HashMap,
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+use web3::{helpers, BatchTransport, RequestId, Transport};
+
+/// An HTTP endpoint, shared by all its clones
+#[derive(Debug, Clone)]
+pub struct HttpEndpoint {
+    client: Client,
+    url: Url,
+    id: Arc<AtomicUsize>,
+}
+
+impl HttpEndpoint {
+    /// The endpoint at `url`
+    pub fn new(url: &str) -> web3::Result<HttpEndpoint> {
+        let url = url
+            .parse()
+            .map_err(|e| web3::Error::InvalidResponse(format!("Invalid url {}: {}", url, e)))?;
+        let client = Client::builder().build().map_err(io_error)?;
+        Ok(HttpEndpoint {
+            client,
+            url,
+            id: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// The url of the endpoint
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Post a request, returns the body of the response
+    fn post(&self, request: Request) -> BoxFuture<'static, web3::Result<Response>> {
+        let client = self.client.clone();
+        let url = self.url.clone();
+        async move {
+            let response = client.post(url).json(&request).send().await.map_err(io_error)?;
+            let status = response.status();
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                let retry_after = response
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| retry_after(value, SystemTime::now()));
+                return Err(rate_limited(retry_after));
+            }
+            let body = response.bytes().await.map_err(io_error)?;
+            if !status.is_success() {
+                // the node may refuse a request with a JSON-RPC error in the body, e.g. a 400 or a 500
+                return helpers::to_response_from_slice(&body).map_err(|_| {
+                    let message = format!("HTTP status {}", status);
+                    web3::Error::Io(io::Error::new(io::ErrorKind::Other, message))
+                });
+            }
+            helpers::to_response_from_slice(&body)
+        }
+        .boxed()
+    }
+}
+
+/// The delay of a `Retry-After` header, in seconds or until a date
+fn retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    match value.trim().parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => {
+            let date = httpdate::parse_http_date(value.trim()).ok()?;
+            Some(date.duration_since(now).unwrap_or_default())
+        },
+    }
+}
+
+/// The error of a request refused over the rate limit, the delay is in the `retry_after` field of its data
+fn rate_limited(retry_after: Option<Duration>) -> web3::Error {
+    web3::Error::Rpc(jsonrpc_core::Error {
+        code: ErrorCode::ServerError(429),
+        message: "Too Many Requests".to_owned(),
+        data: retry_after.map(|delay| serde_json::json!({ "retry_after": delay.as_secs_f64() })),
+    })
+}
+
+/// The endpoint could not be reached or its answer could not be read, classified as transient
+fn io_error(err: reqwest::Error) -> web3::Error {
+    web3::Error::Io(io::Error::new(io::ErrorKind::Other, err.to_string()))
+}
+
+impl Transport for HttpEndpoint {
+    type Out = BoxFuture<'static, web3::Result<Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        let id = self.id.fetch_add(1, Ordering::Relaxed);
+        (id, helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: Call) -> Self::Out {
+        let response = self.post(Request::Single(request));
+        async move {
+            match response.await? {
+                Response::Single(output) => helpers::to_result_from_output(output),
+                Response::Batch(_) => Err(web3::Error::InvalidResponse("Batch answer to a single request".to_owned())),
+            }
+        }
+        .boxed()
+    }
+}
+
+impl BatchTransport for HttpEndpoint {
+    type Batch = BoxFuture<'static, web3::Result<Vec<web3::Result<Value>>>>;
+
+    fn send_batch<T>(&self, requests: T) -> Self::Batch
+    where
+        T: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let (ids, calls): (Vec<RequestId>, Vec<Call>) = requests.into_iter().unzip();
+        let response = self.post(Request::Batch(calls));
+        async move {
+            match response.await? {
+                Response::Batch(outputs) => batch_results(&ids, outputs),
+                Response::Single(output) => Err(helpers::to_result_from_output(output)
+                    .err()
+                    .unwrap_or_else(|| web3::Error::InvalidResponse("Single answer to a batch".to_owned()))),
+            }
+        }
+        .boxed()
+    }
+}
+
+/// The results of a batch in the order of its requests, the node may answer them in any order
+fn batch_results(ids: &[RequestId], outputs: Vec<Output>) -> web3::Result<Vec<web3::Result<Value>>> {
+    let mut outputs: HashMap<RequestId, Output> = outputs
+        .into_iter()
+        .filter_map(|output| match output.id() {
+            jsonrpc_core::Id::Num(id) => Some((*id as RequestId, output)),
+            _ => None,
+        })
+        .collect();
+    ids.iter()
+        .map(|id| {
+            outputs
+                .remove(id)
+                .map(helpers::to_result_from_output)
+                .ok_or_else(|| web3::Error::InvalidResponse(format!("No answer to the request {} of the batch", id)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_after() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_445_412_480);
+        assert_eq!(Some(Duration::from_secs(3)), retry_after("3", now));
+        assert_eq!(Some(Duration::from_secs(120)), retry_after("Wed, 21 Oct 2015 07:30:00 GMT", now));
+        // a date in the past asks for no delay
+        assert_eq!(Some(Duration::from_secs(0)), retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now));
+        assert_eq!(None, retry_after("soon", now));
+    }
+
+    #[tokio::test]
+    async fn test_refused_request() {
+        // the JSON-RPC error in the body of a refused request is kept
+        let body = r#"{"jsonrpc":"2.0","id":0,"error":{"code":-32005,"message":"query returned more than 10000 results"}}"#;
+        let endpoint = HttpEndpoint::new(&crate::rpc_stub::serve_status("400 Bad Request", body).await).unwrap();
+        match endpoint.execute("eth_getLogs", vec![]).await {
+            Err(web3::Error::Rpc(err)) => {
+                assert_eq!(ErrorCode::ServerError(-32005), err.code);
+                assert_eq!("query returned more than 10000 results", err.message);
+            }
+            other => panic!("unexpected answer {:?}", other),
+        }
+
+        // the status is returned if the body is not a JSON-RPC answer
+        let endpoint = HttpEndpoint::new(&crate::rpc_stub::serve_status("502 Bad Gateway", "<html></html>").await).unwrap();
+        match endpoint.execute("eth_blockNumber", vec![]).await {
+            Err(web3::Error::Io(err)) => assert_eq!("HTTP status 502 Bad Gateway", err.to_string()),
+            other => panic!("unexpected answer {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batch_in_any_order() {
+        let url = crate::rpc_stub::serve(|method, _params| match method {
+            "eth_blockNumber" => Ok(serde_json::json!("0x1")),
+            _ => Err(jsonrpc_core::Error::method_not_found()),
+        })
+        .await;
+        let endpoint = HttpEndpoint::new(&url).unwrap();
+        let requests = vec![endpoint.prepare("eth_blockNumber", vec![]), endpoint.prepare("eth_chainId", vec![])];
+        let results = endpoint.send_batch(requests).await.unwrap();
+        assert_eq!(serde_json::json!("0x1"), *results[0].as_ref().unwrap());
+        assert!(matches!(results[1], Err(web3::Error::Rpc(_))));
+
+        // the node may answer the requests of a batch in any order
+        let outputs = |ids: &[u64]| -> Vec<Output> {
+            ids.iter()
+                .map(|id| serde_json::from_value(serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": id })).unwrap())
+                .collect()
+        };
+        let results = batch_results(&[0, 1], outputs(&[1, 0])).unwrap();
+        assert_eq!(serde_json::json!(0), *results[0].as_ref().unwrap());
+        assert_eq!(serde_json::json!(1), *results[1].as_ref().unwrap());
+        assert!(batch_results(&[0, 1], outputs(&[1])).is_err());
+    }
+}
@@ -6,22 +6,124 @@
 mod error;
 mod evm_client;
 
+/// http transport of the rpc endpoints
+pub mod http;
+pub use http::HttpEndpoint;
+
+/// transport over several rpc endpoints
+pub mod transport;
+pub use transport::FailoverTransport;
+pub use transport::RpcTimeouts;
+
+/// cache of contract classifications and metadata
+pub mod contract_cache;
+pub use contract_cache::ContractCache;
+pub use contract_cache::ContractInfo;
+
+/// bytecode based capability fingerprinting
+pub mod fingerprint;
+pub use fingerprint::ContractFeature;
+pub use fingerprint::ContractFingerprint;
+
+/// watchlist of the wallet-centric tracking
+pub mod watchlist;
+pub use watchlist::Watchlist;
+
 /// helper to get evm nft events
 pub mod events_helper;
 pub use events_helper::Event;
 pub use events_helper::Erc721Event;
 pub use events_helper::Erc1155Event;
+pub use events_helper::Erc5192Event;
+pub use events_helper::EventsConfig;
+pub use events_helper::TransferKind;
+pub use events_helper::Standard;
+pub use events_helper::decode_log;
+
+/// pluggable classification of the decoded events
+pub mod classifier;
+pub use classifier::Classifier;
+pub use classifier::classify_events;
+
+/// the versioned serialization schema of the events
+pub mod schema;
+pub use schema::VersionedEvent;
+pub use schema::EVENT_SCHEMA_VERSION;
+
+/// enumeration of ERC721Enumerable collections
+pub mod enumeration;
+pub use enumeration::Erc721Token;
+pub use enumeration::Erc721TokenCallback;
+
+/// retry policy of the failed requests
+pub mod retry;
+pub use retry::RetryPolicy;
+
+/// client-side rate limiting of the rpc endpoints
+pub mod rate_limit;
+pub use rate_limit::RateLimit;
+pub use rate_limit::RateLimiter;
+
+/// checkpoints of the tracker cursors
+pub mod checkpoint;
+pub use checkpoint::CheckpointStore;
+pub use checkpoint::FileCheckpointStore;
+pub use checkpoint::MemoryCheckpointStore;
 
 /// the events tracker
 pub mod tracker;
 pub use tracker::Erc721EventCallback;
 pub use tracker::Erc1155EventCallback;
+pub use tracker::TrackerConfig;
+pub use tracker::EventOrigin;
+
+/// the sinks which receive the tracked events batch by batch
+pub mod sink;
+pub use sink::EventBatch;
+pub use sink::EventSink;
+pub use sink::TrackedEvent;
+
+/// the tracked events as a stream
+pub mod event_stream;
+pub use event_stream::CancelHandle;
+pub use event_stream::track_stream;
+
+/// the builder of the tracker
+pub mod builder;
+pub use builder::Tracker;
+pub use builder::TrackerBuilder;
+pub use builder::TrackerState;
+pub use builder::TrackerStatus;
+
+/// quarantine of the logs which keep failing
+pub mod quarantine;
+pub use quarantine::DeadLetter;
+pub use quarantine::DeadLetterStore;
+pub use quarantine::FileDeadLetterStore;
+pub use quarantine::MemoryDeadLetterStore;
+
+/// graceful shutdown of the tracker
+pub mod shutdown;
 
-pub use error::Error;
+/// parallel backfill of the historical events
+pub mod backfill;
+pub use backfill::BackfillConfig;
+
+/// callbacks which print the events, shared by the executables
+pub mod println_callbacks;
+
+/// local json-rpc node of the tests
+#[cfg(test)]
+mod rpc_stub;
+
+pub use error::{Error, Recovery};
 /// The lib's result
 pub type Result<T> = std::result::Result<T, Error>;
 
 pub use evm_client::EvmClient;
+pub use evm_client::NftQuery;
+pub use evm_client::NftQueryResult;
+pub use evm_client::TopicFilter;
 
 
 #[macro_use]
@@ -30,10 +132,19 @@ extern crate log;
 #[macro_use]
 extern crate async_trait;
 
-use web3::{transports::Http, Web3};
+use futures::StreamExt;
+use web3::types::H160;
+
+/// The client of the `rpc` endpoint, with the timeouts and the quota of the config
+fn rpc_client(chain_name: &str, rpc: &str, rpc_timeouts: &RpcTimeouts, rate_limit: &RateLimit) -> Result<EvmClient> {
+    Ok(EvmClient::with_transport(chain_name.to_owned(), FailoverTransport::from_urls(&[rpc])?)
+        .with_timeouts(rpc_timeouts.clone())
+        .with_rate_limits(std::slice::from_ref(rate_limit)))
+}
 
 /// This is the entry function for this library.
 /// This function wraps the logic for tracking erc721 and erc1155 transfers.
+/// On `SIGINT` or `SIGTERM`, the tracking stops once the current range is done, see `shutdown`.
 pub async fn start_tracking(
     chain_name: &str,
     rpc: &str,
@@ -42,12 +153,149 @@ pub async fn start_tracking(
     erc721_cb: &mut dyn Erc721EventCallback,
     erc1155_cb: &mut dyn Erc1155EventCallback,
 ) -> Result<()> {
-    let web3 = Web3::new(Http::new(rpc)?);
-    let client = EvmClient::new(chain_name.to_owned(), web3);
+    let tracker_config = TrackerConfig::new(start_from, step);
+    let checkpoints = MemoryCheckpointStore::default();
+    start_tracking_with_config(chain_name, rpc, &tracker_config, &EventsConfig::default(), &checkpoints, erc721_cb, erc1155_cb).await
+}
+
+/// The same as `start_tracking`, the blocks are scanned according to `tracker_config` and the
+/// events are built according to `config`, e.g. only the events of an allowlist of contracts are tracked.
+/// The tracking resumes from the checkpoint saved in `checkpoints`, which is flushed once the tracking stops.
+/// Returns the error which halted the tracking, see `Error::recovery`, or an error if the current
+/// range is not done within the drain timeout after a shutdown.
+pub async fn start_tracking_with_config(
+    chain_name: &str,
+    rpc: &str,
+    tracker_config: &TrackerConfig,
+    config: &EventsConfig,
+    checkpoints: &dyn CheckpointStore,
+    erc721_cb: &mut dyn Erc721EventCallback,
+    erc1155_cb: &mut dyn Erc1155EventCallback,
+) -> Result<()> {
+    let client = rpc_client(chain_name, rpc, &tracker_config.rpc_timeouts, &tracker_config.rate_limit)?;
 
-    tracker::track_events(&client, start_from, step, None, erc721_cb, erc1155_cb).await;
+    shutdown::shutdown_on_signals(&tracker_config.shutdown);
+    let tracking = tracker::track_events(&client, config, tracker_config, checkpoints, erc721_cb, erc1155_cb);
+    shutdown::drain(tracking, &tracker_config.shutdown, tracker_config.drain_timeout).await?;
+    checkpoints.flush()
+}
 
-    Ok(())
+/// This is the entry function for tracking events into a sink.
+/// The sink receives the events range by range and acknowledges them, the checkpoints saved in
+/// `checkpoints` only advance after the acknowledgments. Returns the error of the sink if it fails
+/// more than `tracker_config.sink_max_attempts` times.
+/// On `SIGINT` or `SIGTERM`, the tracking stops once the current range is done and the sink is flushed.
+pub async fn start_tracking_with_sink(
+    chain_name: &str,
+    rpc: &str,
+    tracker_config: &TrackerConfig,
+    config: &EventsConfig,
+    checkpoints: &dyn CheckpointStore,
+    sink: &mut dyn EventSink,
+) -> Result<()> {
+    let client = rpc_client(chain_name, rpc, &tracker_config.rpc_timeouts, &tracker_config.rate_limit)?;
+
+    shutdown::shutdown_on_signals(&tracker_config.shutdown);
+    let tracking = tracker::track_events_to_sink(&client, config, tracker_config, checkpoints, sink);
+    shutdown::drain(tracking, &tracker_config.shutdown, tracker_config.drain_timeout).await
+}
+
+/// This is the entry function for following the head from now while the history is backfilled from
+/// `tracker_config.start_from`, see `TrackerConfig::concurrent_backfill`.
+/// The live and the historical cursors save their own checkpoints in `checkpoints`, the callbacks are told
+/// the origin of each event. The historical cursor stops at the block where the live cursor started.
+pub async fn start_tracking_with_backfill(
+    chain_name: &str,
+    rpc: &str,
+    tracker_config: &TrackerConfig,
+    config: &EventsConfig,
+    checkpoints: &dyn CheckpointStore,
+    erc721_cb: &mut dyn Erc721EventCallback,
+    erc1155_cb: &mut dyn Erc1155EventCallback,
+) -> Result<()> {
+    let client = rpc_client(chain_name, rpc, &tracker_config.rpc_timeouts, &tracker_config.rate_limit)?;
+
+    let mut tracker_config = tracker_config.clone();
+    tracker_config.concurrent_backfill = true;
+    shutdown::shutdown_on_signals(&tracker_config.shutdown);
+    let tracking = tracker::track_events(&client, config, &tracker_config, checkpoints, erc721_cb, erc1155_cb);
+    shutdown::drain(tracking, &tracker_config.shutdown, tracker_config.drain_timeout).await?;
+    checkpoints.flush()
+}
+
+/// This is the entry function for backfilling the historical events of `[start, end]` with concurrent workers.
+/// Each shard saves its checkpoint in `checkpoints`, an interrupted backfill resumes from them.
+/// On `SIGINT` or `SIGTERM`, the backfill stops once the current range is done.
+pub async fn start_backfill(
+    chain_name: &str,
+    rpc: &str,
+    backfill_config: &BackfillConfig,
+    config: &EventsConfig,
+    checkpoints: &dyn CheckpointStore,
+    erc721_cb: &mut dyn Erc721EventCallback,
+    erc1155_cb: &mut dyn Erc1155EventCallback,
+) -> Result<()> {
+    let client = rpc_client(chain_name, rpc, &backfill_config.rpc_timeouts, &backfill_config.rate_limit)?;
+
+    shutdown::shutdown_on_signals(&backfill_config.shutdown);
+    let backfill = backfill::backfill_events(&client, config, backfill_config, checkpoints, erc721_cb, erc1155_cb);
+    shutdown::drain(backfill, &backfill_config.shutdown, backfill_config.drain_timeout).await?;
+    checkpoints.flush()
+}
+
+/// This is the entry function for replaying the logs quarantined in `dead_letters`.
+/// The events of each log are passed to the callbacks as historical events and its letter is
+/// removed, a log which still fails keeps its letter. Returns the number of replayed logs.
+/// The requests are sent within `rpc_timeouts` and `rate_limit`, as the tracking does.
+pub async fn replay_dead_letters(
+    chain_name: &str,
+    rpc: &str,
+    config: &EventsConfig,
+    retry_policy: &RetryPolicy,
+    rpc_timeouts: &RpcTimeouts,
+    rate_limit: &RateLimit,
+    dead_letters: &dyn DeadLetterStore,
+    erc721_cb: &mut dyn Erc721EventCallback,
+    erc1155_cb: &mut dyn Erc1155EventCallback,
+) -> Result<usize> {
+    let client = rpc_client(chain_name, rpc, rpc_timeouts, rate_limit)?;
+
+    quarantine::replay(&client, config, retry_policy, dead_letters, erc721_cb, erc1155_cb).await
 }
 
+/// This is the entry function for taking a one-shot snapshot of an ERC721Enumerable collection.
+/// Every token of the collection is passed to the callback with its owner and metadata.
+/// The tokens which can not be fetched are logged and skipped.
+/// The requests are sent within `rpc_timeouts` and `rate_limit`, as the tracking does.
+pub async fn snapshot_collection(
+    chain_name: &str,
+    rpc: &str,
+    address: H160,
+    concurrency: usize,
+    rpc_timeouts: &RpcTimeouts,
+    rate_limit: &RateLimit,
+    erc721_token_cb: &mut dyn Erc721TokenCallback,
+) -> Result<()> {
+    let client = rpc_client(chain_name, rpc, rpc_timeouts, rate_limit)?;
+
+    let name = client.get_erc721_name(&address).await.unwrap_or("Unknown".to_owned());
+    let symbol = client.get_erc721_symbol(&address).await.unwrap_or("Unknown".to_owned());
+
+    let tokens = enumeration::snapshot_collection(&client, address, concurrency, None).await?;
+    futures::pin_mut!(tokens);
+    let mut count = 0;
+    while let Some(token) = tokens.next().await {
+        match token {
+            Ok(token) => {
+                erc721_token_cb.on_erc721_token(token, name.clone(), symbol.clone()).await;
+                count += 1;
+            }
+            Err(err) => {
+                error!("Encountered an error when enumerate {:?}: {:?}", address, err);
+            }
+        }
+    }
+    info!("{} tokens of {:?} found", count, address);
 
+    Ok(())
+}
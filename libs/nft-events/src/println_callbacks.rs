@@ -0,0 +1,68 @@
+//! This module contains the callbacks which print the events and the tokens to the stdout,
+//! shared by the executables of all the chains.
+use crate::{Erc1155Event, Erc1155EventCallback, Erc5192Event, Erc721Event, Erc721EventCallback, Erc721Token, Erc721TokenCallback, EventOrigin};
+
+/// Prints the ERC721 and ERC5192 events
+pub struct PrintlnErc721EventCallback {}
+
+#[async_trait]
+impl Erc721EventCallback for PrintlnErc721EventCallback {
+    async fn on_erc721_event(
+        &mut self,
+        event: Erc721Event,
+        name: String,
+        symbol: String,
+        token_uri: String,
+        origin: EventOrigin,
+    ) {
+        println!("------------------------------------------------------------------------------------------");
+        println!("{} event: {:?}", origin.as_str(), event);
+        println!(
+            "name: {:?}, symbol: {:?}, token_uri: {:?}",
+            name, symbol, token_uri
+        );
+    }
+
+    async fn on_erc5192_event(
+        &mut self,
+        event: Erc5192Event,
+        origin: EventOrigin,
+    ) {
+        println!("==========================================================================================");
+        println!("{} event: {:?}", origin.as_str(), event);
+    }
+}
+
+/// Prints the ERC1155 events
+pub struct PrintlnErc1155EventCallback {}
+
+#[async_trait]
+impl Erc1155EventCallback for PrintlnErc1155EventCallback {
+    async fn on_erc1155_event(
+        &mut self,
+        event: Erc1155Event,
+        token_uri: String,
+        origin: EventOrigin,
+    ) {
+        println!("++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++");
+        println!("{} event: {:?}", origin.as_str(), event);
+        println!("token_uri: {:?}", token_uri);
+    }
+}
+
+/// Prints the tokens of a snapshot
+pub struct PrintlnErc721TokenCallback {}
+
+#[async_trait]
+impl Erc721TokenCallback for PrintlnErc721TokenCallback {
+    async fn on_erc721_token(
+        &mut self,
+        token: Erc721Token,
+        name: String,
+        symbol: String,
+    ) {
+        println!("******************************************************************************************");
+        println!("token: {:?}", token);
+        println!("name: {:?}, symbol: {:?}", name, symbol);
+    }
+}
@@ -0,0 +1,411 @@
+//! This module contains the quarantine of the poison ranges.
+//! A range which still fails after `quarantine_after` attempts is bisected down to the logs which
+//! fail alone. These logs are written with their error to a `DeadLetterStore` and the rest of the
+//! range is tracked. The dead letters are replayed later, e.g. once the node or the contract is
+//! fixed. Only the errors of the logs quarantine them, see `Error::is_log_error`: the errors of the
+//! node, e.g. a missing block or state, never quarantine a log.
+use crate::{
+    events_helper,
+    retry::RetryPolicy,
+    tracker::{self, EventOrigin},
+    Erc1155EventCallback, Erc721EventCallback, Error, Event, EventsConfig, EvmClient, Result, TrackerConfig,
+};
+use futures::future::{BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fmt::Debug,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use tokio::time::sleep;
+use web3::types::Log;
+
+/// The default number of attempts of a range before its bad logs are quarantined
+pub const DEFAULT_QUARANTINE_AFTER: u32 = 5;
+
+/// A log which failed alone, with its error
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// The cursor which scanned the log
+    pub cursor: String,
+    /// The log
+    pub log: Log,
+    /// The last error of the log
+    pub error: String,
+}
+
+impl DeadLetter {
+    /// Identifies the log, `<block_number>-<transaction_hash>-<log_index>`
+    pub fn key(&self) -> String {
+        format!(
+            "{}-{:?}-{}",
+            self.log.block_number.unwrap_or_default(),
+            self.log.transaction_hash.unwrap_or_default(),
+            self.log.log_index.unwrap_or_default()
+        )
+    }
+}
+
+/// Where the dead letters are kept
+pub trait DeadLetterStore: Debug + Send + Sync {
+    /// Add a dead letter, or replace the letter of the same log
+    fn put(&self, letter: &DeadLetter) -> Result<()>;
+
+    /// All the dead letters, in `(block_number, log_index)` order
+    fn list(&self) -> Result<Vec<DeadLetter>>;
+
+    /// Remove the letter of a log
+    fn remove(&self, letter: &DeadLetter) -> Result<()>;
+}
+
+/// The dead letters are kept in memory and lost on exit, shared by all the clones of the store
+#[derive(Debug, Clone, Default)]
+pub struct MemoryDeadLetterStore {
+    letters: Arc<Mutex<BTreeMap<String, DeadLetter>>>,
+}
+
+impl DeadLetterStore for MemoryDeadLetterStore {
+    fn put(&self, letter: &DeadLetter) -> Result<()> {
+        self.letters.lock().unwrap().insert(letter.key(), letter.clone());
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<DeadLetter>> {
+        let mut letters: Vec<DeadLetter> = self.letters.lock().unwrap().values().cloned().collect();
+        letters.sort_by_key(|letter| (letter.log.block_number, letter.log.log_index));
+        Ok(letters)
+    }
+
+    fn remove(&self, letter: &DeadLetter) -> Result<()> {
+        self.letters.lock().unwrap().remove(&letter.key());
+        Ok(())
+    }
+}
+
+/// Each dead letter is saved as JSON in the file `<key>.json` of a directory
+#[derive(Debug, Clone)]
+pub struct FileDeadLetterStore {
+    dir: PathBuf,
+}
+
+impl FileDeadLetterStore {
+    /// Save the dead letters in `dir`, which is created if it does not exist
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Result<FileDeadLetterStore> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|e| Error::Other(format!("Failed to create {:?}: {}", dir, e)))?;
+        Ok(FileDeadLetterStore { dir })
+    }
+
+    fn path(&self, letter: &DeadLetter) -> PathBuf {
+        self.dir.join(format!("{}.json", letter.key()))
+    }
+}
+
+impl DeadLetterStore for FileDeadLetterStore {
+    fn put(&self, letter: &DeadLetter) -> Result<()> {
+        let path = self.path(letter);
+        let tmp_path = path.with_extension("json.tmp");
+        let content = serde_json::to_string_pretty(letter).map_err(|e| Error::Other(e.to_string()))?;
+        fs::write(&tmp_path, content)
+            .and_then(|_| fs::rename(&tmp_path, &path))
+            .map_err(|e| Error::Other(format!("Failed to write {:?}: {}", path, e)))
+    }
+
+    fn list(&self) -> Result<Vec<DeadLetter>> {
+        let entries = fs::read_dir(&self.dir).map_err(|e| Error::Other(format!("Failed to read {:?}: {}", self.dir, e)))?;
+        let mut letters = vec![];
+        for entry in entries {
+            let path = entry.map_err(|e| Error::Other(e.to_string()))?.path();
+            if path.extension() != Some(OsStr::new("json")) {
+                continue;
+            }
+            let content = fs::read_to_string(&path).map_err(|e| Error::Other(format!("Failed to read {:?}: {}", path, e)))?;
+            let letter: DeadLetter = serde_json::from_str(&content)
+                .map_err(|e| Error::Other(format!("Invalid dead letter in {:?}: {}", path, e)))?;
+            letters.push(letter);
+        }
+        letters.sort_by_key(|letter| (letter.log.block_number, letter.log.log_index));
+        Ok(letters)
+    }
+
+    fn remove(&self, letter: &DeadLetter) -> Result<()> {
+        let path = self.path(letter);
+        if !path.exists() {
+            return Ok(());
+        }
+        fs::remove_file(&path).map_err(|e| Error::Other(format!("Failed to remove {:?}: {}", path, e)))
+    }
+}
+
+/// The events of `from` - `to` without the logs which still fail alone after `quarantine_after`
+/// attempts, these logs are put in the `dead_letters` of `tracker_config`.
+/// Returns the error of the node if it fails, the range is fetched again later.
+pub(crate) async fn quarantine_range(
+    evm_client: &EvmClient,
+    config: &EventsConfig,
+    tracker_config: &TrackerConfig,
+    cursor: &str,
+    from: u64,
+    to: u64,
+) -> Result<Vec<Event>> {
+    let logs = events_helper::get_logs(evm_client, config, from, to).await?;
+    let attempts = tracker_config.quarantine_after.unwrap_or(1).max(1);
+    let process = |logs: Vec<Log>| events_helper::get_events_of_logs(evm_client, config, logs).boxed();
+    let (events, bad_logs) = bisect(logs, attempts, &tracker_config.retry_policy, &process).await?;
+
+    for (log, err) in bad_logs {
+        let letter = DeadLetter {
+            cursor: cursor.to_owned(),
+            log,
+            error: err.to_string(),
+        };
+        error!("Quarantine the log {} of {}: {}", letter.key(), cursor, err);
+        tracker_config.dead_letters.put(&letter)?;
+    }
+    Ok(events)
+}
+
+/// The output of the good logs and the bad logs with their error
+type Bisected<T> = (Vec<T>, Vec<(Log, Error)>);
+
+/// Split the logs which failed together in halves until the logs which fail alone are found.
+/// A log which fails alone is tried `attempts` times with the delays of `policy`.
+/// Returns the output of the other logs in order and the bad logs with their error, or the first
+/// error of the node, which is not the fault of the logs.
+fn bisect<'a, T, F>(
+    logs: Vec<Log>,
+    attempts: u32,
+    policy: &'a RetryPolicy,
+    process: &'a F,
+) -> BoxFuture<'a, Result<Bisected<T>>>
+where
+    T: Send + 'a,
+    F: Fn(Vec<Log>) -> BoxFuture<'a, Result<Vec<T>>> + Sync,
+{
+    async move {
+        if logs.len() > 1 {
+            let mut left = logs;
+            let right = left.split_off(left.len() / 2);
+            let (mut output, mut bad_logs) = process_or_bisect(left, attempts, policy, process).await?;
+            let (mut right_output, mut right_bad_logs) = process_or_bisect(right, attempts, policy, process).await?;
+            output.append(&mut right_output);
+            bad_logs.append(&mut right_bad_logs);
+            return Ok((output, bad_logs));
+        }
+
+        let log = match logs.into_iter().next() {
+            Some(log) => log,
+            None => return Ok((vec![], vec![])),
+        };
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match process(vec![log.clone()]).await {
+                Ok(output) => return Ok((output, vec![])),
+                Err(err) if !err.is_log_error() => return Err(err),
+                Err(err) => match policy.next_delay(&err, attempt) {
+                    Some(delay) if attempt < attempts => sleep(delay).await,
+                    _ => return Ok((vec![], vec![(log, err)])),
+                },
+            }
+        }
+    }
+    .boxed()
+}
+
+/// Process the logs together, they are bisected if one of them fails
+fn process_or_bisect<'a, T, F>(
+    logs: Vec<Log>,
+    attempts: u32,
+    policy: &'a RetryPolicy,
+    process: &'a F,
+) -> BoxFuture<'a, Result<Bisected<T>>>
+where
+    T: Send + 'a,
+    F: Fn(Vec<Log>) -> BoxFuture<'a, Result<Vec<T>>> + Sync,
+{
+    async move {
+        if logs.len() > 1 {
+            match process(logs.clone()).await {
+                Ok(output) => return Ok((output, vec![])),
+                Err(err) if !err.is_log_error() => return Err(err),
+                Err(_) => (),
+            }
+        }
+        bisect(logs, attempts, policy, process).await
+    }
+    .boxed()
+}
+
+/// Track the logs of the dead letters again, their events are passed to the callbacks as
+/// historical events and the letters are removed. A log which still fails keeps its letter, with
+/// its new error. Returns the number of replayed logs.
+pub async fn replay(
+    evm_client: &EvmClient,
+    config: &EventsConfig,
+    retry_policy: &RetryPolicy,
+    dead_letters: &dyn DeadLetterStore,
+    erc721_cb: &mut dyn Erc721EventCallback,
+    erc1155_cb: &mut dyn Erc1155EventCallback,
+) -> Result<usize> {
+    let mut replayed = 0;
+    for letter in dead_letters.list()? {
+        let tracked = match events_helper::get_events_of_logs(evm_client, config, vec![letter.log.clone()]).await {
+            Ok(events) => {
+                let origin = EventOrigin::Historical;
+                tracker::collect_events(evm_client, events, origin, config.concurrency.max(1), retry_policy).await
+            },
+            Err(err) => Err(err),
+        };
+        match tracked {
+            Ok(tracked) => {
+                for tracked in tracked {
+                    tracker::call_callbacks(tracked, erc721_cb, erc1155_cb).await;
+                }
+                dead_letters.remove(&letter)?;
+                info!("Replayed the log {} of {}", letter.key(), letter.cursor);
+                replayed += 1;
+            },
+            Err(err) => {
+                error!("The log {} of {} still fails: {}", letter.key(), letter.cursor, err);
+                dead_letters.put(&DeadLetter {
+                    error: err.to_string(),
+                    ..letter
+                })?;
+            },
+        }
+    }
+    Ok(replayed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use web3::types::{Bytes, H160, H256};
+
+    fn log(log_index: u64) -> Log {
+        Log {
+            address: H160::from_low_u64_be(1),
+            topics: vec![],
+            data: Bytes(vec![]),
+            block_hash: Some(H256::from_low_u64_be(3)),
+            block_number: Some(100.into()),
+            transaction_hash: Some(H256::from_low_u64_be(2)),
+            transaction_index: Some(4.into()),
+            log_index: Some(log_index.into()),
+            transaction_log_index: None,
+            log_type: None,
+            removed: None,
+        }
+    }
+
+    fn letter(log_index: u64) -> DeadLetter {
+        DeadLetter {
+            cursor: "main".to_owned(),
+            log: log(log_index),
+            error: "invalid".to_owned(),
+        }
+    }
+
+    /// The log indexes of the logs, fails if one of them is in `bad`
+    fn process_indexes<'a>(logs: Vec<Log>, bad: &'a [u64], err: fn() -> Error) -> BoxFuture<'a, Result<Vec<u64>>> {
+        async move {
+            let indexes: Vec<u64> = logs.iter().map(|log| log.log_index.unwrap().as_u64()).collect();
+            if indexes.iter().any(|index| bad.contains(index)) {
+                Err(err())
+            } else {
+                Ok(indexes)
+            }
+        }
+        .boxed()
+    }
+
+    fn fatal() -> Error {
+        Error::ExecutionError {
+            address: H160::from_low_u64_be(1),
+            method: "supportsInterface".to_owned(),
+            message: "out of gas".to_owned(),
+        }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new(Duration::from_millis(1), Duration::from_millis(1))
+    }
+
+    #[tokio::test]
+    async fn test_bisect_to_bad_logs() {
+        let logs: Vec<Log> = (0..10).map(log).collect();
+        let bad = [3, 7];
+        let sizes = Mutex::new(vec![]);
+        let process = |logs: Vec<Log>| {
+            sizes.lock().unwrap().push(logs.len());
+            process_indexes(logs, &bad, fatal)
+        };
+        let policy = policy();
+        let (output, bad_logs) = bisect(logs, 2, &policy, &process).await.unwrap();
+        assert_eq!(vec![0, 1, 2, 4, 5, 6, 8, 9], output);
+        // the range which failed is split right away
+        assert!(sizes.lock().unwrap().iter().all(|size| *size <= 5));
+        let bad_indexes: Vec<u64> = bad_logs.iter().map(|(log, _)| log.log_index.unwrap().as_u64()).collect();
+        assert_eq!(vec![3, 7], bad_indexes);
+    }
+
+    #[tokio::test]
+    async fn test_bisect_node_error() {
+        let logs: Vec<Log> = (0..10).map(log).collect();
+        let bad = [3];
+        let unreachable = || Error::rpc(web3::Error::Unreachable, "eth_call");
+        let process = |logs: Vec<Log>| process_indexes(logs, &bad, unreachable);
+        let policy = policy();
+        let result = bisect(logs.clone(), 2, &policy, &process).await;
+        assert!(matches!(result, Err(Error::TransientRpcError { .. })));
+
+        // the node misses the state of the block, the log is not at fault
+        let header_not_found = || {
+            let err = jsonrpc_core::Error {
+                code: jsonrpc_core::ErrorCode::ServerError(-32000),
+                message: "header not found".to_owned(),
+                data: None,
+            };
+            Error::rpc(web3::Error::Rpc(err), "eth_call")
+        };
+        let process = |logs: Vec<Log>| process_indexes(logs, &bad, header_not_found);
+        let result = bisect(logs, 2, &policy, &process).await;
+        assert!(matches!(result, Err(Error::RpcError { .. })));
+    }
+
+    #[test]
+    fn test_memory_dead_letter_store() {
+        let store = MemoryDeadLetterStore::default();
+        store.put(&letter(7)).unwrap();
+        store.put(&letter(3)).unwrap();
+        store.put(&letter(3)).unwrap();
+        let indexes: Vec<u64> = store.list().unwrap().iter().map(|l| l.log.log_index.unwrap().as_u64()).collect();
+        assert_eq!(vec![3, 7], indexes);
+
+        store.remove(&letter(3)).unwrap();
+        assert_eq!(1, store.clone().list().unwrap().len());
+    }
+
+    #[test]
+    fn test_file_dead_letter_store() {
+        let dir = std::env::temp_dir().join(format!("nft-events-dead-letters-{}", std::process::id()));
+        let store = FileDeadLetterStore::new(&dir).unwrap();
+        store.put(&letter(7)).unwrap();
+        store.put(&letter(3)).unwrap();
+
+        let letters = FileDeadLetterStore::new(&dir).unwrap().list().unwrap();
+        assert_eq!(vec![letter(3).key(), letter(7).key()], letters.iter().map(|l| l.key()).collect::<Vec<String>>());
+        assert_eq!("invalid", letters[0].error);
+
+        store.remove(&letter(3)).unwrap();
+        store.remove(&letter(3)).unwrap();
+        assert_eq!(1, store.list().unwrap().len());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+//! This module contains the client-side rate limiting of the RPC endpoints.
+//! Each endpoint has a token bucket which is refilled at `requests_per_second` up to `burst` tokens.
+//! A request takes a token, a batch takes one token per request, and waits while the bucket is in
+//! debt. The number of requests in flight is capped by `max_concurrent`. When the provider refuses
+//! a request over its limit, the endpoint is paused for the delay it asks or for the `cooldown`.
+use crate::retry::millis;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::{sleep_until, Instant},
+};
+
+/// The default pause of an endpoint which refused a request over its rate limit without a delay
+pub const DEFAULT_RATE_LIMIT_COOLDOWN: Duration = Duration::from_secs(5);
+
+/// The quota of an RPC endpoint, there is no limit by default.
+/// In `config.toml`, every field is optional:
+///
+/// ```toml
+/// [rate_limit]
+/// requests_per_second = 10.0
+/// burst = 20
+/// max_concurrent = 4
+/// cooldown_ms = 5000
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimit {
+    /// The number of requests sent per second on average, not limited if it is `None`
+    pub requests_per_second: Option<f64>,
+    /// The number of requests sent at once after a quiet period, `requests_per_second` rounded up if it is `None`
+    pub burst: Option<u32>,
+    /// The number of requests in flight at the same time, not limited if it is `None`
+    pub max_concurrent: Option<usize>,
+    /// The pause of the endpoint after it refused a request over its rate limit, if it did not ask for a delay
+    #[serde(rename = "cooldown_ms", with = "millis")]
+    pub cooldown: Duration,
+}
+
+impl RateLimit {
+    /// Up to `requests_per_second` requests per second, with the default burst and without a concurrency cap
+    pub fn new(requests_per_second: f64) -> RateLimit {
+        RateLimit {
+            requests_per_second: Some(requests_per_second),
+            ..RateLimit::default()
+        }
+    }
+
+    /// The size of the token bucket
+    pub fn burst(&self) -> f64 {
+        match (self.burst, self.requests_per_second) {
+            (Some(burst), _) => burst as f64,
+            (None, Some(requests_per_second)) => requests_per_second.ceil().max(1.0),
+            (None, None) => f64::INFINITY,
+        }
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            requests_per_second: None,
+            burst: None,
+            max_concurrent: None,
+            cooldown: DEFAULT_RATE_LIMIT_COOLDOWN,
+        }
+    }
+}
+
+/// The state of a token bucket
+#[derive(Debug)]
+struct Bucket {
+    /// Negative while the requests already let through wait for their tokens
+    tokens: f64,
+    updated: Instant,
+    /// The end of the pause asked by the provider
+    paused_until: Option<Instant>,
+}
+
+/// Enforces the `RateLimit` of an endpoint, shared by all its clones
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    bucket: Arc<Mutex<Bucket>>,
+    concurrency: Option<Arc<Semaphore>>,
+}
+
+/// Allows a request in flight, the slot of the concurrency cap is released when it is dropped
+#[derive(Debug)]
+pub struct RatePermit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl RateLimiter {
+    /// A limiter with a full bucket
+    pub fn new(limit: RateLimit) -> RateLimiter {
+        let bucket = Bucket {
+            tokens: limit.burst(),
+            updated: Instant::now(),
+            paused_until: None,
+        };
+        RateLimiter {
+            concurrency: limit.max_concurrent.map(|max| Arc::new(Semaphore::new(max.max(1)))),
+            bucket: Arc::new(Mutex::new(bucket)),
+            limit,
+        }
+    }
+
+    /// The quota of this limiter
+    pub fn limit(&self) -> &RateLimit {
+        &self.limit
+    }
+
+    /// Wait until `cost` requests can be sent, e.g. the size of a batch.
+    /// The requests are let through in the order they asked.
+    pub async fn acquire(&self, cost: usize) -> RatePermit {
+        let permit = match &self.concurrency {
+            Some(semaphore) => Some(semaphore.clone().acquire_owned().await.expect("never closed")),
+            None => None,
+        };
+        let ready_at = self.reserve(cost as f64);
+        if ready_at > Instant::now() {
+            sleep_until(ready_at).await;
+        }
+        RatePermit { _permit: permit }
+    }
+
+    /// Pause the endpoint for `delay`, or for the `cooldown` if it is `None`
+    pub fn pause(&self, delay: Option<Duration>) {
+        let until = Instant::now() + delay.unwrap_or(self.limit.cooldown);
+        let mut bucket = self.bucket.lock().unwrap();
+        if !matches!(bucket.paused_until, Some(paused_until) if paused_until >= until) {
+            bucket.paused_until = Some(until);
+        }
+    }
+
+    /// Take the tokens of `cost` requests, returns when they can be sent
+    fn reserve(&self, cost: f64) -> Instant {
+        let now = Instant::now();
+        let mut bucket = self.bucket.lock().unwrap();
+        let mut ready_at = match bucket.paused_until {
+            Some(paused_until) if paused_until > now => paused_until,
+            _ => now,
+        };
+        if let Some(requests_per_second) = self.limit.requests_per_second {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * requests_per_second).min(self.limit.burst());
+            bucket.updated = now;
+            bucket.tokens -= cost;
+            if bucket.tokens < 0.0 {
+                ready_at = std::cmp::max(ready_at, now + Duration::from_secs_f64(-bucket.tokens / requests_per_second));
+            }
+        }
+        ready_at
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter::new(RateLimit::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The timer of tokio rounds the sleeps up to the next millisecond
+    fn assert_elapsed(start: Instant, millis: u128) {
+        let elapsed = start.elapsed().as_millis();
+        assert!(elapsed >= millis && elapsed <= millis + 1, "{} ms elapsed instead of {} ms", elapsed, millis);
+    }
+
+    #[tokio::test]
+    async fn test_token_bucket() {
+        tokio::time::pause();
+        let start = Instant::now();
+        let limiter = RateLimiter::new(RateLimit::new(10.0));
+        for _ in 0..10 {
+            limiter.acquire(1).await;
+        }
+        assert_elapsed(start, 0);
+
+        // the bucket is empty, a token comes every 100 ms
+        limiter.acquire(1).await;
+        assert_elapsed(start, 100);
+
+        // a batch of 20 requests takes 20 tokens
+        limiter.acquire(20).await;
+        assert_elapsed(start, 2100);
+
+        let unlimited = RateLimiter::default();
+        unlimited.acquire(1000).await;
+        assert_elapsed(start, 2100);
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent() {
+        tokio::time::pause();
+        let limiter = RateLimiter::new(RateLimit {
+            max_concurrent: Some(1),
+            ..RateLimit::default()
+        });
+        let permit = limiter.acquire(1).await;
+        assert!(tokio::time::timeout(Duration::from_secs(1), limiter.acquire(1)).await.is_err());
+        drop(permit);
+        tokio::time::timeout(Duration::from_secs(1), limiter.acquire(1)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pause() {
+        tokio::time::pause();
+        let start = Instant::now();
+        let limiter = RateLimiter::default();
+        limiter.pause(Some(Duration::from_secs(2)));
+        // a shorter pause does not shorten it
+        limiter.pause(Some(Duration::from_secs(1)));
+        limiter.acquire(1).await;
+        assert_eq!(2, start.elapsed().as_secs());
+
+        limiter.pause(None);
+        limiter.acquire(1).await;
+        assert_eq!(2 + DEFAULT_RATE_LIMIT_COOLDOWN.as_secs(), start.elapsed().as_secs());
+    }
+}
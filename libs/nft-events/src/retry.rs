@@ -0,0 +1,231 @@
+//! This module contains the retry policy of the tracker.
+//! A failed request is sent again after an exponential backoff: the delay starts at `base_delay`,
+//! doubles after each failed attempt up to `max_delay`, and is spread by a random `jitter` so the
+//! trackers of the same provider do not retry at the same time. The errors whose `Recovery` is
+//! not to retry, like reverted calls, are returned at once.
+use crate::{Error, Recovery, Result};
+use futures::Future;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// The default delay before the second attempt
+pub const DEFAULT_RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// The default maximum delay between two attempts
+pub const DEFAULT_RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// The default part of the delay which is random
+pub const DEFAULT_RETRY_JITTER: f64 = 0.2;
+
+/// When a failed request is sent again.
+/// In `config.toml`, the delays are in milliseconds and every field is optional:
+///
+/// ```toml
+/// [retry]
+/// base_delay_ms = 1000
+/// max_delay_ms = 60000
+/// jitter = 0.2
+/// max_attempts = 10
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// The delay before the second attempt
+    #[serde(rename = "base_delay_ms", with = "millis")]
+    pub base_delay: Duration,
+    /// The maximum delay between two attempts
+    #[serde(rename = "max_delay_ms", with = "millis")]
+    pub max_delay: Duration,
+    /// The part of the delay which is random, from `0.0` to `1.0`.
+    /// A delay `d` becomes a random delay between `d * (1 - jitter)` and `d`.
+    pub jitter: f64,
+    /// The number of attempts before the error is returned, the request is retried forever if it is `None`
+    pub max_attempts: Option<u32>,
+}
+
+impl RetryPolicy {
+    /// Retry forever with an exponential backoff from `base_delay` to `max_delay`, with the default jitter
+    pub fn new(base_delay: Duration, max_delay: Duration) -> RetryPolicy {
+        RetryPolicy {
+            base_delay,
+            max_delay,
+            jitter: DEFAULT_RETRY_JITTER,
+            max_attempts: None,
+        }
+    }
+
+    /// The delay after `attempts` failed attempts, without the jitter
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempts.saturating_sub(1)).unwrap_or(u32::MAX);
+        std::cmp::min(self.base_delay.saturating_mul(factor), self.max_delay)
+    }
+
+    /// The delay after `attempts` failed attempts, with the jitter
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let backoff = self.backoff(attempts);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return backoff;
+        }
+        backoff.mul_f64(1.0 - rand::thread_rng().gen_range(0.0..=jitter))
+    }
+
+    /// The delay before the next attempt after `err`, `None` if the error must be returned:
+    /// it is not retried according to its `Recovery`, or it was the last attempt.
+    /// The delay asked by a provider is honored if it is longer than the backoff.
+    pub fn next_delay(&self, err: &Error, attempts: u32) -> Option<Duration> {
+        if let Some(max_attempts) = self.max_attempts {
+            if attempts >= max_attempts {
+                return None;
+            }
+        }
+        match err.recovery() {
+            Recovery::Retry => Some(self.delay(attempts)),
+            Recovery::RetryAfter(delay) => Some(std::cmp::max(delay, self.delay(attempts))),
+            Recovery::SplitRange | Recovery::Skip | Recovery::Halt => None,
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(DEFAULT_RETRY_BASE_DELAY, DEFAULT_RETRY_MAX_DELAY)
+    }
+}
+
+/// Run `operation` until it succeeds or `policy` returns its error, `what` names it in the logs
+pub async fn retry<T, F, Fut>(policy: &RetryPolicy, what: &str, mut operation: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match operation().await {
+            Ok(value) => return Ok(value),
+            Err(err) => match policy.next_delay(&err, attempts) {
+                Some(delay) => {
+                    warn!("Failed to {} (attempt {}): {}, retry in {:?}.", what, attempts, err, delay);
+                    sleep(delay).await;
+                },
+                None => return Err(err),
+            },
+        }
+    }
+}
+
+/// (De)serialize a `Duration` as a number of milliseconds
+pub(crate) mod millis {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        Ok(Duration::from_millis(u64::deserialize(deserializer)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tokio::time::Instant;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            jitter: 0.0,
+            ..RetryPolicy::new(Duration::from_secs(1), Duration::from_secs(10))
+        }
+    }
+
+    /// Fails with `err` the first `failures` times
+    async fn flaky(calls: &AtomicU32, failures: u32, err: fn() -> Error) -> Result<u32> {
+        let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+        if call <= failures {
+            Err(err())
+        } else {
+            Ok(call)
+        }
+    }
+
+    fn unreachable() -> Error {
+        Error::rpc(web3::Error::Unreachable, "eth_blockNumber")
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = policy();
+        let delays: Vec<u64> = (1..=6).map(|attempts| policy.backoff(attempts).as_secs()).collect();
+        assert_eq!(vec![1, 2, 4, 8, 10, 10], delays);
+        assert_eq!(Duration::from_secs(10), policy.backoff(100));
+
+        let jittered = RetryPolicy { jitter: 0.5, ..policy };
+        for attempts in 1..10 {
+            let delay = jittered.delay(attempts);
+            assert!(delay <= jittered.backoff(attempts) && delay >= jittered.backoff(attempts) / 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_with_backoff() {
+        tokio::time::pause();
+        let start = Instant::now();
+        let calls = AtomicU32::new(0);
+        let result = retry(&policy(), "get the head", || flaky(&calls, 3, unreachable)).await;
+        assert_eq!(4, result.unwrap());
+        // 1 + 2 + 4 seconds of backoff
+        assert_eq!(7, start.elapsed().as_secs());
+    }
+
+    #[tokio::test]
+    async fn test_retry_gives_up() {
+        tokio::time::pause();
+        let start = Instant::now();
+        let calls = AtomicU32::new(0);
+        let policy = RetryPolicy { max_attempts: Some(3), ..policy() };
+        let result = retry(&policy, "get the head", || flaky(&calls, 5, unreachable)).await;
+        assert!(matches!(result, Err(Error::TransientRpcError { .. })));
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+        assert_eq!(3, start.elapsed().as_secs());
+    }
+
+    #[tokio::test]
+    async fn test_retry_honors_recovery() {
+        tokio::time::pause();
+        let start = Instant::now();
+
+        // a reverted call is not retried
+        let calls = AtomicU32::new(0);
+        let reverted = || Error::RevertedCall {
+            address: Default::default(),
+            method: "tokenURI".to_owned(),
+            message: "execution reverted".to_owned(),
+        };
+        assert!(retry(&policy(), "get the token URI", || flaky(&calls, 1, reverted)).await.is_err());
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+        assert_eq!(0, start.elapsed().as_secs());
+
+        // the delay asked by the provider is longer than the backoff
+        let calls = AtomicU32::new(0);
+        let rate_limited = || Error::RateLimited {
+            context: "eth_getLogs".to_owned(),
+            retry_after: Some(Duration::from_secs(30)),
+        };
+        assert_eq!(2, retry(&policy(), "get the logs", || flaky(&calls, 1, rate_limited)).await.unwrap());
+        assert_eq!(30, start.elapsed().as_secs());
+    }
+
+    #[test]
+    fn test_deserialize_policy() {
+        let policy: RetryPolicy = serde_json::from_str(r#"{"base_delay_ms": 500, "max_attempts": 5}"#).unwrap();
+        assert_eq!(Duration::from_millis(500), policy.base_delay);
+        assert_eq!(DEFAULT_RETRY_MAX_DELAY, policy.max_delay);
+        assert_eq!(Some(5), policy.max_attempts);
+    }
+}
@@ -0,0 +1,169 @@
+//! This module contains a local JSON-RPC node for the tests.
+//! Each request is answered by a handler with the result or the error of its method, the batches
+//! are answered request by request. The connections are kept alive like the ones of a real node.
+use jsonrpc_core::{Error, Value};
+use std::sync::Arc;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Answers a method called with its params
+pub type Handler = Arc<dyn Fn(&str, &[Value]) -> Result<Value, Error> + Send + Sync>;
+
+/// Serve the handler on a local port, returns the url of the node
+pub async fn serve<F>(handler: F) -> String
+where
+    F: Fn(&str, &[Value]) -> Result<Value, Error> + Send + Sync + 'static,
+{
+    let handler: Handler = Arc::new(handler);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        while let Ok((connection, _)) = listener.accept().await {
+            tokio::spawn(serve_connection(connection, handler.clone()));
+        }
+    });
+    url
+}
+
+/// Serve a fixed answer with `status` and `body` to every request on a local port, returns the url of the node
+pub async fn serve_status(status: &'static str, body: &'static str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        while let Ok((mut connection, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut buffer = vec![];
+                while find(&buffer, b"\r\n\r\n").is_none() {
+                    if !read(&mut connection, &mut buffer).await {
+                        return;
+                    }
+                }
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = connection.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    url
+}
+
+async fn serve_connection(mut connection: TcpStream, handler: Handler) {
+    let mut buffer = vec![];
+    loop {
+        let (head, length) = loop {
+            if let Some(end) = find(&buffer, b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&buffer[..end]).to_lowercase();
+                let length = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .and_then(|length| length.trim().parse::<usize>().ok())
+                    .unwrap_or(0);
+                break (end + 4, length);
+            }
+            if !read(&mut connection, &mut buffer).await {
+                return;
+            }
+        };
+        while buffer.len() < head + length {
+            if !read(&mut connection, &mut buffer).await {
+                return;
+            }
+        }
+        let body: Value = serde_json::from_slice(&buffer[head..head + length]).unwrap_or(Value::Null);
+        buffer.drain(..head + length);
+
+        let answer = match body {
+            Value::Array(requests) => Value::Array(requests.iter().map(|request| answer(&handler, request)).collect()),
+            request => answer(&handler, &request),
+        };
+        let answer = answer.to_string();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            answer.len(),
+            answer
+        );
+        if connection.write_all(response.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn answer(handler: &Handler, request: &Value) -> Value {
+    let method = request["method"].as_str().unwrap_or_default();
+    let params = request["params"].as_array().cloned().unwrap_or_default();
+    match handler(method, &params) {
+        Ok(result) => serde_json::json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }),
+        Err(error) => serde_json::json!({ "jsonrpc": "2.0", "id": request["id"], "error": error }),
+    }
+}
+
+async fn read(connection: &mut TcpStream, buffer: &mut Vec<u8>) -> bool {
+    let mut chunk = [0; 4096];
+    match connection.read(&mut chunk).await {
+        Ok(0) | Err(_) => false,
+        Ok(n) => {
+            buffer.extend_from_slice(&chunk[..n]);
+            true
+        },
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// A block without transactions returned by `eth_getBlockByNumber`
+pub fn block(number: u64, timestamp: u64) -> Value {
+    let zero = format!("0x{:064x}", 0);
+    serde_json::json!({
+        "hash": format!("0x{:064x}", number + 1),
+        "parentHash": zero,
+        "sha3Uncles": zero,
+        "miner": format!("0x{:040x}", 0),
+        "stateRoot": zero,
+        "transactionsRoot": zero,
+        "receiptsRoot": zero,
+        "number": format!("0x{:x}", number),
+        "gasUsed": "0x0",
+        "gasLimit": "0x0",
+        "extraData": "0x",
+        "logsBloom": format!("0x{}", "0".repeat(512)),
+        "timestamp": format!("0x{:x}", timestamp),
+        "difficulty": "0x0",
+        "totalDifficulty": "0x0",
+        "sealFields": [],
+        "uncles": [],
+        "transactions": [],
+        "size": "0x0",
+        "mixHash": zero,
+        "nonce": "0x0000000000000000",
+    })
+}
+
+/// The ABI encoding of a `bool` returned by `eth_call`
+pub fn encode_bool(value: bool) -> Value {
+    Value::String(format!("0x{:064x}", value as u8))
+}
+
+
+/// The ABI encoding of a `uint256` or an `address` returned by `eth_call`
+pub fn encode_word(value: u64) -> Value {
+    Value::String(format!("0x{:064x}", value))
+}
+
+/// The ABI encoding of a `string` returned by `eth_call`
+pub fn encode_string(value: &str) -> Value {
+    let mut data = format!("0x{:064x}{:064x}", 32, value.len());
+    let mut bytes = value.as_bytes().to_vec();
+    bytes.resize((value.len() + 31) / 32 * 32, 0);
+    for byte in bytes {
+        data.push_str(&format!("{:02x}", byte));
+    }
+    Value::String(data)
+}
@@ -0,0 +1,141 @@
+//! This module contains the serialization schema of the events.
+//! The events are serialized with serde, the addresses are EIP-55 checksummed strings and
+//! the `U256` values (token ids and amounts) are decimal strings, so nothing is truncated.
+//! A serialized event is wrapped in a `VersionedEvent`, `schema_version` is increased on
+//! every breaking change of the schema.
+use crate::Event;
+use serde::{Deserialize, Serialize};
+use web3::{signing::keccak256, types::H160};
+
+/// The version of the event schema
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+/// An event with the version of its schema
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionedEvent {
+    /// The version of the schema, `EVENT_SCHEMA_VERSION` for the events built by this library
+    pub schema_version: u32,
+    /// The event, flattened into the same object
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl From<Event> for VersionedEvent {
+    fn from(event: Event) -> Self {
+        VersionedEvent {
+            schema_version: EVENT_SCHEMA_VERSION,
+            event,
+        }
+    }
+}
+
+/// Format an address with the EIP-55 mixed-case checksum
+pub fn to_checksum_address(address: &H160) -> String {
+    let lowercase: String = address.as_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+    let hash = keccak256(lowercase.as_bytes());
+
+    let mut checksummed = String::from("0x");
+    for (i, c) in lowercase.chars().enumerate() {
+        let nibble = if i % 2 == 0 { hash[i / 2] >> 4 } else { hash[i / 2] & 0x0f };
+        if nibble >= 8 {
+            checksummed.push(c.to_ascii_uppercase());
+        } else {
+            checksummed.push(c);
+        }
+    }
+    checksummed
+}
+
+/// (De)serialize an `H160` as an EIP-55 checksummed string
+pub(crate) mod checksum_address {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use std::str::FromStr;
+    use web3::types::H160;
+
+    pub fn serialize<S: Serializer>(address: &H160, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::to_checksum_address(address))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<H160, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        H160::from_str(s.trim_start_matches("0x")).map_err(|e| D::Error::custom(format!("invalid address {}: {}", s, e)))
+    }
+}
+
+/// (De)serialize a `U256` as a decimal string
+pub(crate) mod u256_dec {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use web3::types::U256;
+
+    pub fn serialize<S: Serializer>(value: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        U256::from_dec_str(&s).map_err(|e| D::Error::custom(format!("invalid decimal {}: {:?}", s, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Erc1155Event, TransferKind};
+    use std::str::FromStr;
+    use web3::types::{H256, U256};
+
+    #[test]
+    fn test_to_checksum_address() {
+        // test vectors of EIP-55
+        for expected in &[
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+        ] {
+            let address = H160::from_str(&expected[2..]).unwrap();
+            assert_eq!(*expected, to_checksum_address(&address));
+        }
+    }
+
+    #[test]
+    fn test_versioned_event_round_trip() {
+        let amount = U256::MAX;
+        let event = Event::Erc1155(Erc1155Event {
+            block_number: Some(100),
+            block_hash: Some(H256::from_low_u64_be(1)),
+            block_timestamp: Some(1629525603),
+            address: H160::from_str("5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").unwrap(),
+            transaction_hash: Some(H256::from_low_u64_be(2)),
+            transaction_index: Some(3),
+            log_index: Some(4),
+            removed: false,
+            operator: H160::from_low_u64_be(5),
+            from: H160::zero(),
+            to: H160::from_low_u64_be(6),
+            token_id: U256::from(7),
+            amount,
+            kind: TransferKind::Mint,
+            batch_index: 0,
+        });
+
+        let value = serde_json::to_value(&VersionedEvent::from(event)).unwrap();
+        assert_eq!(1, value["schema_version"]);
+        assert_eq!("erc1155", value["type"]);
+        assert_eq!("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed", value["address"]);
+        assert_eq!(amount.to_string(), value["amount"]);
+        assert_eq!("7", value["token_id"]);
+        assert_eq!("mint", value["kind"]);
+
+        let versioned: VersionedEvent = serde_json::from_value(value).unwrap();
+        assert_eq!(EVENT_SCHEMA_VERSION, versioned.schema_version);
+        match versioned.event {
+            Event::Erc1155(e) => {
+                assert_eq!(amount, e.amount);
+                assert_eq!(Some(1629525603), e.block_timestamp);
+                assert_eq!(TransferKind::Mint, e.kind);
+            },
+            _ => panic!("not an erc1155 event"),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! This module contains the graceful shutdown of the tracker.
+//! When the shutdown handle of a `TrackerConfig` is cancelled, e.g. on `SIGINT` or `SIGTERM`, the
+//! tracker finishes the range it is delivering, saves its checkpoint, flushes the sinks and returns.
+//! A range which is not done within the drain timeout is aborted, it is tracked again on the next run.
+use crate::{event_stream::CancelHandle, Error, Result};
+use futures::Future;
+use std::time::Duration;
+
+/// The default time given to the current range to be done after a shutdown
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Cancel `handle` on the first `SIGINT` or `SIGTERM`, `Ctrl-C` on the platforms without signals
+pub fn shutdown_on_signals(handle: &CancelHandle) {
+    let handle = handle.clone();
+    tokio::spawn(async move {
+        wait_for_signal().await;
+        info!("Shutdown requested, finishing the current range");
+        handle.cancel();
+    });
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {},
+                _ = terminate.recv() => {},
+            }
+        },
+        Err(err) => {
+            error!("Failed to listen to SIGTERM: {:?}", err);
+            let _ = tokio::signal::ctrl_c().await;
+        },
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Run the tracking until it returns by itself, or until `drain_timeout` after `shutdown` is cancelled.
+/// The tracking is dropped with the range it was delivering if it is not done in time.
+pub(crate) async fn drain<F>(tracking: F, shutdown: &CancelHandle, drain_timeout: Duration) -> Result<()>
+where
+    F: Future<Output = Result<()>>,
+{
+    tokio::pin!(tracking);
+    tokio::select! {
+        result = &mut tracking => result,
+        _ = shutdown.cancelled() => {
+            match tokio::time::timeout(drain_timeout, &mut tracking).await {
+                Ok(result) => result,
+                Err(_) => Err(Error::DrainTimeout { timeout: drain_timeout }),
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn tracking(range: Duration) -> Result<()> {
+        tokio::time::sleep(range).await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        let shutdown = CancelHandle::new();
+        shutdown.cancel();
+        drain(tracking(Duration::from_millis(10)), &shutdown, Duration::from_secs(1)).await.unwrap();
+        let aborted = drain(tracking(Duration::from_secs(10)), &shutdown, Duration::from_millis(10)).await;
+        assert!(matches!(aborted, Err(Error::DrainTimeout { .. })));
+
+        // without a shutdown, the tracking is not limited
+        let running = CancelHandle::new();
+        drain(tracking(Duration::from_millis(20)), &running, Duration::from_millis(1)).await.unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+//! This module contains the sinks of the tracker.
+//! A sink receives the events of a range of blocks at once, in their `(block_number, log_index)`
+//! order, and acknowledges them by returning `Ok`. The checkpoint of the range is saved only after
+//! the acknowledgment, a range whose delivery failed is delivered again.
+use crate::{tracker::EventOrigin, Error, Event, Result};
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// An event with its metadata and origin
+#[derive(Debug, Clone)]
+pub struct TrackedEvent {
+    /// The event
+    pub event: Event,
+    /// Found near the head or by backfilling
+    pub origin: EventOrigin,
+    /// The name of the ERC721 collection
+    pub name: Option<String>,
+    /// The symbol of the ERC721 collection
+    pub symbol: Option<String>,
+    /// The token URI of the ERC721 and ERC1155 tokens
+    pub token_uri: Option<String>,
+}
+
+/// The events of a range of blocks of a cursor
+#[derive(Debug, Clone)]
+pub struct EventBatch {
+    /// The cursor which scanned the range
+    pub cursor: String,
+    /// The first block of the range
+    pub from: u64,
+    /// The last block of the range
+    pub to: u64,
+    /// The events of the range, in `(block_number, log_index)` order.
+    /// A range without events is delivered too, it acknowledges the progress of the cursor.
+    pub events: Vec<TrackedEvent>,
+}
+
+/// Receives the tracked events batch by batch
+#[async_trait]
+pub trait EventSink: Send {
+    /// Handle the events of a range.
+    /// Returns `Ok` once the events are safely handled, the checkpoint of the range is saved after it.
+    /// On error, the same batch is delivered again or the tracking halts, see `TrackerConfig`.
+    async fn on_batch(&mut self, batch: &EventBatch) -> Result<()>;
+
+    /// Write what the sink still buffers, called when the tracking stops.
+    /// Does nothing by default.
+    async fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The default delay before a failed batch is delivered again
+pub const DEFAULT_SINK_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Deliver a batch to the sink, up to `max_attempts` times if any, forever otherwise
+pub(crate) async fn deliver(
+    sink: &mut dyn EventSink,
+    batch: &EventBatch,
+    max_attempts: Option<u32>,
+    retry_delay: Duration,
+) -> Result<()> {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match sink.on_batch(batch).await {
+            Ok(()) => return Ok(()),
+            Err(err) => {
+                if let Some(max_attempts) = max_attempts {
+                    if attempts >= max_attempts {
+                        return Err(Error::SinkError {
+                            cursor: batch.cursor.clone(),
+                            from: batch.from,
+                            to: batch.to,
+                            attempts,
+                            reason: err.to_string(),
+                        });
+                    }
+                }
+                error!(
+                    "The sink failed on {} - {} of {}: {:?}, retry in {:?}.",
+                    batch.from, batch.to, batch.cursor, err, retry_delay
+                );
+                sleep(retry_delay).await;
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fails the first `failures` batches
+    struct FlakySink {
+        failures: u32,
+        delivered: Vec<(u64, u64)>,
+    }
+
+    #[async_trait]
+    impl EventSink for FlakySink {
+        async fn on_batch(&mut self, batch: &EventBatch) -> Result<()> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(Error::Other("unavailable".to_owned()));
+            }
+            self.delivered.push((batch.from, batch.to));
+            Ok(())
+        }
+    }
+
+    fn batch() -> EventBatch {
+        EventBatch {
+            cursor: "main".to_owned(),
+            from: 100,
+            to: 105,
+            events: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deliver_retries() {
+        let mut sink = FlakySink { failures: 2, delivered: vec![] };
+        deliver(&mut sink, &batch(), Some(3), Duration::from_millis(1)).await.unwrap();
+        assert_eq!(vec![(100, 105)], sink.delivered);
+
+        let mut sink = FlakySink { failures: 2, delivered: vec![] };
+        deliver(&mut sink, &batch(), None, Duration::from_millis(1)).await.unwrap();
+        assert_eq!(vec![(100, 105)], sink.delivered);
+    }
+
+    #[tokio::test]
+    async fn test_deliver_halts() {
+        let mut sink = FlakySink { failures: 2, delivered: vec![] };
+        let result = deliver(&mut sink, &batch(), Some(2), Duration::from_millis(1)).await;
+        assert!(matches!(result, Err(Error::SinkError { attempts: 2, from: 100, to: 105, .. })));
+        assert!(sink.delivered.is_empty());
+    }
+}
@@ -1,6 +1,24 @@
-use crate::{Error as MyError, events_helper, Event, Erc721Event, Erc1155Event, EvmClient};
-use std::{error::Error, time::Duration};
-use tokio::time::sleep;
+use crate::{
+    event_stream::{self, CancelHandle},
+    quarantine::{self, DeadLetterStore, MemoryDeadLetterStore, DEFAULT_QUARANTINE_AFTER},
+    shutdown::DEFAULT_DRAIN_TIMEOUT,
+    retry::{retry, RetryPolicy},
+    sink::{self, EventBatch, EventSink, TrackedEvent, DEFAULT_SINK_RETRY_DELAY},
+    Recovery, events_helper, CheckpointStore, Error, Event, EventsConfig, Erc721Event, Erc1155Event,
+    Erc5192Event, EvmClient, RateLimit, Result, RpcTimeouts, TransferKind,
+};
+use futures::{
+    future::{try_join_all, BoxFuture, FutureExt},
+    stream::{self, BoxStream},
+    StreamExt,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, sync::Arc, time::Duration};
+use tokio::{
+    sync::mpsc,
+    time::{sleep, timeout},
+};
+use web3::types::H160;
 
 /// When the ERC721 event is fetched, the event will be exposed to the caller through this trait.
 /// The caller needs to implement this trait and write the code on how to use the event.
@@ -14,7 +32,17 @@ pub trait Erc721EventCallback: Send {
         name: String,
         symbol: String,
         token_uri: String,
+        origin: EventOrigin,
     );
+
+    /// The callback function for the ERC5192 `Locked` and `Unlocked` events.
+    /// Does nothing by default.
+    async fn on_erc5192_event(
+        &mut self,
+        _event: Erc5192Event,
+        _origin: EventOrigin,
+    ) {
+    }
 }
 
 /// When the ERC1155 event is fetched, the event will be exposed to the caller through this trait.
@@ -26,118 +54,1041 @@ pub trait Erc1155EventCallback: Send {
     async fn on_erc1155_event(
         &mut self, 
         event: Erc1155Event, 
-        token_uri: String
+        token_uri: String,
+        origin: EventOrigin,
     );
 }
 
+/// Whether an event was found by following the head of the chain or by backfilling its history
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventOrigin {
+    /// Found near the head, soon after it happened
+    Live,
+    /// Found by backfilling the history
+    Historical,
+}
+
+impl EventOrigin {
+    /// The name of the origin, used by the sinks
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventOrigin::Live => "live",
+            EventOrigin::Historical => "historical",
+        }
+    }
+}
+
+/// The default number of ranges fetched ahead while the callbacks of a range run
+pub const DEFAULT_PREFETCH: usize = 4;
+
+/// The default number of ranges spooled by a cursor waiting for its turn, see `TrackerConfig::spool`
+pub const DEFAULT_SPOOL: usize = 64;
+
+/// The default number of blocks behind the head which are not scanned yet, they may still be reorganized
+pub const DEFAULT_CONFIRMATIONS: u64 = 6;
+
+/// The default time to wait for new blocks once the tracking has caught up with the head
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The default time given to fetch the events of a range
+pub const DEFAULT_RANGE_DEADLINE: Duration = Duration::from_secs(600);
+
+/// How the blocks are scanned
+#[derive(Debug, Clone)]
+pub struct TrackerConfig {
+    /// The name of the cursor, its checkpoint is saved under this name
+    pub cursor: String,
+    /// The first block to scan if the cursor has no checkpoint
+    pub start_from: u64,
+    /// The number of blocks of a range
+    pub step: u64,
+    /// The last block to scan, the tracking never ends if it is `None`
+    pub end_block: Option<u64>,
+    /// The number of blocks behind the head which are not scanned yet
+    pub confirmations: u64,
+    /// The time to wait for new blocks once the tracking has caught up with the head
+    pub poll_interval: Duration,
+    /// The maximum number of ranges fetched ahead while the callbacks of a range run
+    pub prefetch: usize,
+    /// The maximum number of ranges kept in memory by a cursor waiting for its turn when the
+    /// cursors are delivered in order, its fetching waits once they are spooled
+    pub spool: usize,
+    /// Follow the head from now while the history is backfilled from `start_from`.
+    /// The live cursor starts at the head on the first run, a historical cursor named
+    /// `<cursor>-historical` backfills the blocks before it. Ignored if `end_block` is set.
+    pub concurrent_backfill: bool,
+    /// The number of times a batch is delivered to the sink before the tracking halts.
+    /// The delivery is retried forever if it is `None`.
+    pub sink_max_attempts: Option<u32>,
+    /// The delay before a failed batch is delivered again
+    pub sink_retry_delay: Duration,
+    /// Stops the tracking once the current range is done, shared by the clones of the config
+    pub shutdown: CancelHandle,
+    /// The time given to the current range to be done after a shutdown, see `shutdown::drain`
+    pub drain_timeout: Duration,
+    /// When the failed requests of the head, the logs and the metadata are sent again
+    pub retry_policy: RetryPolicy,
+    /// The number of failed attempts of a range before the logs which fail alone are quarantined
+    /// in `dead_letters`, the range is retried according to the `retry_policy` if it is `None`.
+    /// The range is quarantined even if the `retry_policy` gives up at the same attempt.
+    /// Only the errors of the logs quarantine a range, see `Error::is_log_error`: the errors of the node are retried.
+    pub quarantine_after: Option<u32>,
+    /// Where the quarantined logs are kept, shared by the clones of the config
+    pub dead_letters: Arc<dyn DeadLetterStore>,
+    /// The time given to an endpoint to answer, by method
+    pub rpc_timeouts: RpcTimeouts,
+    /// The quota of the rpc endpoint of the entry functions, not limited by default
+    pub rate_limit: RateLimit,
+    /// The time given to fetch the events of a range before it is fetched again according to the
+    /// `retry_policy`, there is no deadline if it is `None`
+    pub range_deadline: Option<Duration>,
+}
+
+impl TrackerConfig {
+    /// Scan from `start_from` to the head by ranges of `step` blocks, with the `main` cursor
+    pub fn new(start_from: u64, step: u64) -> TrackerConfig {
+        TrackerConfig {
+            cursor: "main".to_owned(),
+            start_from,
+            step,
+            end_block: None,
+            confirmations: DEFAULT_CONFIRMATIONS,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            prefetch: DEFAULT_PREFETCH,
+            spool: DEFAULT_SPOOL,
+            concurrent_backfill: false,
+            sink_max_attempts: None,
+            sink_retry_delay: DEFAULT_SINK_RETRY_DELAY,
+            shutdown: CancelHandle::new(),
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            retry_policy: RetryPolicy::default(),
+            quarantine_after: Some(DEFAULT_QUARANTINE_AFTER),
+            dead_letters: Arc::new(MemoryDeadLetterStore::default()),
+            rpc_timeouts: RpcTimeouts::default(),
+            rate_limit: RateLimit::default(),
+            range_deadline: Some(DEFAULT_RANGE_DEADLINE),
+        }
+    }
+}
+
+/// The events of a range of blocks
+pub(crate) struct RangeEvents {
+    pub from: u64,
+    pub to: u64,
+    pub events: Vec<Event>,
+}
+
+/// A cursor scans its own blocks and saves its own checkpoint
+pub(crate) struct Cursor {
+    /// The checkpoint is saved under this name
+    pub name: String,
+    /// The first block to scan, after the checkpoint if any
+    pub from: u64,
+    /// The last block to scan, `None` to follow the head
+    pub end: Option<u64>,
+    pub origin: EventOrigin,
+}
+
+/// The first block of a cursor, the block after its checkpoint or `start_from` if it has none.
+/// The checkpoint wins over `start_from`, which is logged when they differ. Returns the error of
+/// the store rather than scanning again from `start_from`.
+pub(crate) fn resume_from(checkpoints: &dyn CheckpointStore, cursor: &str, start_from: u64) -> Result<u64> {
+    match checkpoints.load(cursor)? {
+        Some(checkpoint) => {
+            if checkpoint + 1 != start_from {
+                warn!("Resume {} from the checkpoint {}, not from the block {}", cursor, checkpoint, start_from);
+            } else {
+                info!("Resume {} from the checkpoint {}", cursor, checkpoint);
+            }
+            Ok(checkpoint + 1)
+        },
+        None => Ok(start_from),
+    }
+}
+
 /// Entry function for tracking events.
 /// If you only need to track events, you can use this function directly.
+/// The tracking resumes from the checkpoint of the cursor if there is one.
+/// The events of the next ranges are fetched while the callbacks of a range run, the checkpoint
+/// is saved once all the callbacks of a range have returned.
+/// Returns once the current range is done after `tracker_config.shutdown` is cancelled, or with
+/// the error which halted the tracking.
 pub async fn track_events(
     evm_client: &EvmClient,
-    start_from: u64,
-    step: u64,
-    end_block: Option<u64>,
+    config: &EventsConfig,
+    tracker_config: &TrackerConfig,
+    checkpoints: &dyn CheckpointStore,
+    erc721_cb: &mut dyn Erc721EventCallback,
+    erc1155_cb: &mut dyn Erc1155EventCallback,
+) -> Result<()> {
+    let cursors = async move { Ok((tracker_cursors(evm_client, tracker_config, checkpoints).await?, false)) };
+    let (events, tracking) = event_stream::tracked_events(evm_client, config, tracker_config, cursors, checkpoints);
+    run_callbacks(events, tracking, erc721_cb, erc1155_cb).await
+}
+
+/// Pass the events to the callbacks one by one while the tracking runs, returns the result of the tracking
+pub(crate) async fn run_callbacks(
+    mut events: BoxStream<'_, TrackedEvent>,
+    tracking: BoxFuture<'_, Result<()>>,
+    erc721_cb: &mut dyn Erc721EventCallback,
+    erc1155_cb: &mut dyn Erc1155EventCallback,
+) -> Result<()> {
+    let consumer = async {
+        while let Some(tracked) = events.next().await {
+            call_callbacks(tracked, erc721_cb, erc1155_cb).await;
+        }
+    };
+    let (result, _) = tokio::join!(tracking, consumer);
+    result
+}
+
+/// Pass an event to its callback, the events without their metadata are dropped
+pub(crate) async fn call_callbacks(
+    tracked: TrackedEvent,
     erc721_cb: &mut dyn Erc721EventCallback,
     erc1155_cb: &mut dyn Erc1155EventCallback,
 ) {
+    let TrackedEvent { event, origin, name, symbol, token_uri } = tracked;
+    match (event, name, symbol, token_uri) {
+        (Event::Erc721(e), Some(name), Some(symbol), Some(token_uri)) => {
+            erc721_cb.on_erc721_event(e, name, symbol, token_uri, origin).await;
+        },
+        (Event::Erc1155(e), _, _, Some(token_uri)) => {
+            erc1155_cb.on_erc1155_event(e, token_uri, origin).await;
+        },
+        (Event::Erc5192(e), _, _, _) => {
+            erc721_cb.on_erc5192_event(e, origin).await;
+        },
+        _ => (),
+    }
+}
+
+/// Track the events and deliver them to the sink range by range.
+/// The checkpoint of a range is saved once the sink has acknowledged it. A failed delivery is
+/// retried, the tracking halts with the error after `sink_max_attempts` attempts if it is set.
+pub async fn track_events_to_sink(
+    evm_client: &EvmClient,
+    config: &EventsConfig,
+    tracker_config: &TrackerConfig,
+    checkpoints: &dyn CheckpointStore,
+    sink: &mut dyn EventSink,
+) -> Result<()> {
+    let cursors = tracker_cursors(evm_client, tracker_config, checkpoints).await?;
+    run_cursors(evm_client, config, tracker_config, cursors, false, checkpoints, sink).await
+}
+
+/// The cursors of the tracking, resumed from their checkpoints.
+/// A single live cursor, a historical cursor if `end_block` is set, or both if `concurrent_backfill` is set.
+pub(crate) async fn tracker_cursors(
+    evm_client: &EvmClient,
+    tracker_config: &TrackerConfig,
+    checkpoints: &dyn CheckpointStore,
+) -> Result<Vec<Cursor>> {
+    let name = &tracker_config.cursor;
+    let mut cursors = vec![];
+    if let Some(end_block) = tracker_config.end_block {
+        cursors.push(Cursor {
+            name: name.clone(),
+            from: resume_from(checkpoints, name, tracker_config.start_from)?,
+            end: Some(end_block),
+            origin: EventOrigin::Historical,
+        });
+    } else if tracker_config.concurrent_backfill {
+        let live_start = get_live_start(evm_client, tracker_config, checkpoints).await?;
+        let historical = format!("{}-historical", name);
+        cursors.push(Cursor {
+            name: name.clone(),
+            from: resume_from(checkpoints, name, live_start)?,
+            end: None,
+            origin: EventOrigin::Live,
+        });
+        cursors.push(Cursor {
+            from: resume_from(checkpoints, &historical, tracker_config.start_from)?,
+            name: historical,
+            end: Some(live_start.saturating_sub(1)),
+            origin: EventOrigin::Historical,
+        });
+    } else {
+        cursors.push(Cursor {
+            name: name.clone(),
+            from: resume_from(checkpoints, name, tracker_config.start_from)?,
+            end: None,
+            origin: EventOrigin::Live,
+        });
+    }
+    Ok(cursors)
+}
+
+/// The first block of the live cursor, saved under `<cursor>-live-start` on the first run so the
+/// historical cursor always stops at the same block
+async fn get_live_start(evm_client: &EvmClient, tracker_config: &TrackerConfig, checkpoints: &dyn CheckpointStore) -> Result<u64> {
+    let cursor = &tracker_config.cursor;
+    let key = format!("{}-live-start", cursor);
+    if let Some(live_start) = checkpoints.load(&key)? {
+        return Ok(live_start);
+    }
+
+    let live_start = match checkpoints.load(cursor)? {
+        // the cursor was tracking alone before
+        Some(checkpoint) => checkpoint + 1,
+        None => {
+            let policy = &tracker_config.retry_policy;
+            let latest_block_number = retry(policy, "get the latest block number", || evm_client.get_latest_block_number()).await?;
+            latest_block_number.saturating_sub(tracker_config.confirmations)
+        },
+    };
+    if let Err(err) = checkpoints.save(&key, live_start) {
+        error!("Encountered an error when save {}: {:?}", key, err);
+    }
+    info!("The live cursor {} starts at {}", cursor, live_start);
+    Ok(live_start)
+}
+
+/// Scan the cursors at the same time and deliver their events to the sink.
+/// If `ordered`, the ranges are delivered cursor after cursor, otherwise as soon as they are fetched.
+/// The cursors waiting for their turn keep fetching, up to `spool` of their ranges are kept in memory until then.
+/// The checkpoint of a cursor is saved once the sink has acknowledged its range.
+/// Returns when all the cursors with an end are done, never if a cursor follows the head, unless
+/// the sink fails, an error halts the fetching or the tracking is shut down. The sink and the
+/// checkpoints are flushed before, except on a fetching error.
+pub(crate) async fn run_cursors(
+    evm_client: &EvmClient,
+    config: &EventsConfig,
+    tracker_config: &TrackerConfig,
+    cursors: Vec<Cursor>,
+    ordered: bool,
+    checkpoints: &dyn CheckpointStore,
+    sink: &mut dyn EventSink,
+) -> Result<()> {
+    let mut producers: Vec<BoxFuture<Result<()>>> = vec![];
+    let mut ranges: Vec<BoxStream<(usize, RangeEvents)>> = vec![];
+    for (index, cursor) in cursors.iter().enumerate() {
+        if let Some(end) = cursor.end {
+            if cursor.from > end {
+                info!("{} is done", cursor.name);
+                continue;
+            }
+        }
+
+        // The channel is bounded, the fetching waits when `prefetch` ranges are not processed yet.
+        // A cursor which is not its turn yet spools up to `spool` more ranges until then.
+        let capacity = if ordered && !ranges.is_empty() {
+            tracker_config.prefetch.max(1) + tracker_config.spool
+        } else {
+            tracker_config.prefetch.max(1)
+        };
+        let (sender, receiver) = mpsc::channel(capacity);
+        let (name, from, end, shutdown) = (cursor.name.clone(), cursor.from, cursor.end, &tracker_config.shutdown);
+        producers.push(
+            async move {
+                tokio::select! {
+                    result = fetch_ranges(evm_client, config, tracker_config, &name, from, end, sender) => result,
+                    _ = shutdown.cancelled() => Ok(()),
+                }
+            }
+            .boxed(),
+        );
+        let cursor_ranges = stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|range| (range, receiver))
+        });
+        ranges.push(cursor_ranges.map(move |range| (index, range)).boxed());
+    }
+
+    let ranges: BoxStream<(usize, RangeEvents)> = if ordered {
+        stream::iter(ranges).flatten().boxed()
+    } else {
+        stream::select_all(ranges).boxed()
+    };
+
+    // The ranges are dropped when the consumer returns, which stops the producers
+    let consumer = async move {
+        let mut ranges = ranges;
+        loop {
+            // The shutdown is only checked between the ranges, a range is always delivered in full
+            let next = tokio::select! {
+                biased;
+                _ = tracker_config.shutdown.cancelled() => None,
+                next = ranges.next() => next,
+            };
+            let (index, range) = match next {
+                Some(next) => next,
+                None => break,
+            };
+            let cursor = &cursors[index];
+            let RangeEvents { from, to, events } = range;
+            info!("{} events found in {} - {} by {}", events.len(), from, to, cursor.name);
+
+            let events = collect_events(evm_client, events, cursor.origin, config.concurrency.max(1), &tracker_config.retry_policy).await?;
+            let batch = EventBatch {
+                cursor: cursor.name.clone(),
+                from,
+                to,
+                events,
+            };
+            sink::deliver(sink, &batch, tracker_config.sink_max_attempts, tracker_config.sink_retry_delay).await?;
+
+            if let Err(err) = checkpoints.save(&cursor.name, to) {
+                error!("Encountered an error when save the checkpoint {} of {}: {:?}", to, cursor.name, err);
+            }
+        }
+
+        sink.flush().await?;
+        checkpoints.flush()
+    };
+    tokio::try_join!(try_join_all(producers), consumer).map(|_| ())
+}
+
+/// Fetch the events range by range and send them to the consumer, until `end_block` if any.
+/// The failed requests are retried according to the `retry_policy`, the ranges shrink while the
+/// provider finds them too large and grow back to `step` once it answers.
+/// A range whose logs keep failing is quarantined after `quarantine_after` attempts, see `quarantine`.
+/// A range which is not fetched within the `range_deadline` fails with `Error::Timeout`.
+/// Returns the error which the policy does not retry anymore.
+async fn fetch_ranges(
+    evm_client: &EvmClient,
+    config: &EventsConfig,
+    tracker_config: &TrackerConfig,
+    cursor: &str,
+    start_from: u64,
+    end_block: Option<u64>,
+    sender: mpsc::Sender<RangeEvents>,
+) -> Result<()> {
+    let policy = &tracker_config.retry_policy;
+    let step = tracker_config.step.max(1);
+    let mut range_step = step;
+    let mut attempts = 0;
+    let mut poisoned = false;
     let mut from = start_from;
     loop {
-                // let latest_block_number = evm_client.get_latest_block_number().await.unwrap();
-        match evm_client.get_latest_block_number().await {
-            Ok(latest_block_number) => {
-                let to = std::cmp::min(from + step - 1, latest_block_number - 6);
-                if let Some(end_block) = end_block {
-                    if to > end_block {
+        if let Some(end_block) = end_block {
+            if from > end_block {
+                break;
+            }
+        }
+
+        let latest_block_number = retry(policy, "get the latest block number", || evm_client.get_latest_block_number()).await?;
+        let mut to = std::cmp::min(from + range_step - 1, latest_block_number.saturating_sub(tracker_config.confirmations));
+        if let Some(end_block) = end_block {
+            to = std::cmp::min(to, end_block);
+        }
+
+        if to >= from {
+            info!("Scan in {} - {}({})", from, to, to - from + 1);
+
+            attempts += 1;
+            let fetch = async {
+                if poisoned {
+                    quarantine::quarantine_range(evm_client, config, tracker_config, cursor, from, to).await
+                } else {
+                    events_helper::get_events(evm_client, config, from, to).await
+                }
+            };
+            let result = match tracker_config.range_deadline {
+                Some(deadline) => timeout(deadline, fetch).await.unwrap_or_else(|_| {
+                    Err(Error::Timeout {
+                        context: format!("range {} - {} after {:?}", from, to, deadline),
+                    })
+                }),
+                None => fetch.await,
+            };
+            match result {
+                Ok(events) => {
+                    if sender.send(RangeEvents { from, to, events }).await.is_err() {
+                        // the consumer is gone
                         break;
                     }
+                    from = to + 1;
+                    attempts = 0;
+                    poisoned = false;
+                    range_step = std::cmp::min(range_step * 2, step);
                 }
-
-                if to >= from {
-                    info!("Scan in {} - {}({})", from, to, to - from + 1);
-
-                            // let events = events_helper::get_events(&evm_client, from,
-                            // to).await.unwrap();
-                    match events_helper::get_events(&evm_client, from, to).await {
-                        Ok(events) => {
-
-                            info!("{} events found", events.len());
-
-                            for event in events {
-                                match event {
-                                    Event::Erc721(e) => {
-                                        process_erc721_event(evm_client, e, erc721_cb).await;
-                                    },
-                                    Event::Erc1155(e) => {
-                                        process_erc1155_event(evm_client, e, erc1155_cb).await;
-                                    }
-                                }
+                Err(err) if err.recovery() == Recovery::SplitRange && range_step > 1 => {
+                    range_step /= 2;
+                    attempts = 0;
+                    warn!("{}, scan {} blocks at once.", err, range_step);
+                },
+                Err(err) => {
+                    // the range is quarantined before the retry policy gives up on it
+                    // the errors of the node are retried, they are not the fault of the logs
+                    let quarantine = !poisoned
+                        && err.is_log_error()
+                        && matches!(tracker_config.quarantine_after, Some(n) if attempts >= n);
+                    let delay = if quarantine { Some(policy.delay(attempts)) } else { policy.next_delay(&err, attempts) };
+                    match delay {
+                        Some(delay) => {
+                            error!("Encountered an error when get events (attempt {}): {:?}, wait for {:?}.", attempts, err, delay);
+                            if quarantine {
+                                warn!("{} - {} keeps failing, its bad logs will be quarantined.", from, to);
+                                poisoned = true;
                             }
-
-                            from = to + 1;
-
-                        }
-                        Err(err) => {
-                            process_err(err).await;
+                            sleep(delay).await;
                         },
+                        None => return Err(err),
                     }
-                } else {
-                    debug!("Track events too fast, wait for 30 seconds.");
-                    sleep(Duration::from_secs(30)).await;
-                }
-            }
-            Err(err) => {
-                error!("Encountered an error when get latest_block_number: {:?}, wait for 30 seconds.", err);
-                sleep(Duration::from_secs(30)).await;
+                },
             }
+        } else {
+            debug!("Track events too fast, wait for {:?}.", tracker_config.poll_interval);
+            sleep(tracker_config.poll_interval).await;
         }
     }
+    Ok(())
 }
 
-async fn process_err(err: MyError) {
-    error!("Encountered an error when get events: {:?}, wait for 30 seconds.", err);
-    sleep(Duration::from_secs(30)).await;
+/// The metadata of an event
+enum Metadata {
+    Erc721 { name: String, symbol: String, token_uri: String },
+    Erc1155 { token_uri: String },
+    None,
 }
 
-async fn process_erc721_event(evm_client: &EvmClient, event: Erc721Event, callback: &mut dyn Erc721EventCallback) {
-    if let Some((name, symbol, token_uri)) = get_erc721_metadata(evm_client, &event).await {
-        // callback
-        callback.on_erc721_event(
-            event,
-            name,
-            symbol,
-            token_uri,
-        )
+/// The metadata of the events are fetched at the same time, up to `concurrency` events at once,
+/// but the events keep their `(block_number, log_index)` order.
+/// The name and symbol of each collection are fetched once before.
+/// The failed calls are retried according to `retry_policy`, the ERC721 and ERC1155 events whose
+/// token URI reverts are dropped. Returns the error of a token URI which the policy gives up on.
+pub(crate) async fn collect_events(
+    evm_client: &EvmClient,
+    events: Vec<Event>,
+    origin: EventOrigin,
+    concurrency: usize,
+    retry_policy: &RetryPolicy,
+) -> Result<Vec<TrackedEvent>> {
+    let collections: BTreeSet<H160> = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Erc721(e) => Some(e.address),
+            _ => None,
+        })
+        .collect();
+    stream::iter(collections)
+        .map(|address| async move { get_erc721_name_and_symbol(evm_client, &address, retry_policy).await })
+        .buffer_unordered(concurrency)
+        .collect::<Vec<(String, String)>>()
+        .await;
+
+    let fetched: Vec<(Event, Result<Metadata>)> = stream::iter(events)
+        .map(|event| async move {
+            let metadata = get_metadata(evm_client, &event, retry_policy).await;
+            (event, metadata)
+        })
+        .buffered(concurrency)
+        .collect()
         .await;
+
+    let mut tracked = vec![];
+    for (event, metadata) in fetched {
+        let (name, symbol, token_uri) = match (&event, metadata?) {
+            (Event::Erc721(_), Metadata::Erc721 { name, symbol, token_uri }) => (Some(name), Some(symbol), Some(token_uri)),
+            (Event::Erc1155(_), Metadata::Erc1155 { token_uri }) => (None, None, Some(token_uri)),
+            (Event::Erc5192(_), _) => (None, None, None),
+            _ => continue,
+        };
+        tracked.push(TrackedEvent { event, origin, name, symbol, token_uri });
     }
+    Ok(tracked)
+}
+
+async fn get_metadata(evm_client: &EvmClient, event: &Event, retry_policy: &RetryPolicy) -> Result<Metadata> {
+    let metadata = match event {
+        Event::Erc721(e) => match get_erc721_metadata(evm_client, e, retry_policy).await? {
+            Some((name, symbol, token_uri)) => Metadata::Erc721 { name, symbol, token_uri },
+            None => Metadata::None,
+        },
+        Event::Erc1155(e) => match get_erc1155_metadata(evm_client, e, retry_policy).await? {
+            Some(token_uri) => Metadata::Erc1155 { token_uri },
+            None => Metadata::None,
+        },
+        Event::Erc5192(_) => Metadata::None,
+    };
+    Ok(metadata)
 }
 
+/// `None` if the token URI reverts, the event is skipped. A burn is never skipped, see `get_burned_token_uri`.
 async fn get_erc721_metadata(
     evm_client: &EvmClient,
     event: &Erc721Event,
-) -> Option<(String, String, String)> {
-    if let Ok(token_uri) = evm_client.get_erc721_token_uri(&event.address, &event.token_id).await {
-        let name = evm_client.get_erc721_name(&event.address).await.unwrap_or("Unknown".to_owned());
-        let symbol = evm_client.get_erc721_symbol(&event.address).await.unwrap_or("Unknown".to_owned());
-        return Some((name, symbol, token_uri));
+    retry_policy: &RetryPolicy,
+) -> Result<Option<(String, String, String)>> {
+    let token_uri = if event.kind == TransferKind::Burn {
+        get_burned_token_uri(evm_client, event, retry_policy).await?
+    } else {
+        let token_uri = retry(retry_policy, "get the token URI", || {
+            evm_client.get_erc721_token_uri(&event.address, &event.token_id, None)
+        });
+        match skip_reverted(token_uri.await)? {
+            Some(token_uri) => token_uri,
+            None => return Ok(None),
+        }
+    };
+    let (name, symbol) = get_erc721_name_and_symbol(evm_client, &event.address, retry_policy).await;
+    Ok(Some((name, symbol, token_uri)))
+}
+
+/// The token does not exist anymore at the block of its burn, its token URI is read at the block before.
+/// The burn is delivered with an empty token URI if the token URI is unknown then, e.g. the token was
+/// minted in the same block or the node does not keep the state of the block.
+async fn get_burned_token_uri(evm_client: &EvmClient, event: &Erc721Event, retry_policy: &RetryPolicy) -> Result<String> {
+    let block = event.block_number.map(|block_number| block_number.saturating_sub(1));
+    let token_uri = retry(retry_policy, "get the token URI", || {
+        evm_client.get_erc721_token_uri(&event.address, &event.token_id, block)
+    });
+    match token_uri.await {
+        Ok(token_uri) => Ok(token_uri),
+        Err(err @ Error::RpcError { .. }) => {
+            warn!("The burn of {} of {:?} has no token URI: {}", event.token_id, event.address, err);
+            Ok(String::new())
+        },
+        Err(err) if err.recovery() == Recovery::Skip => {
+            warn!("The burn of {} of {:?} has no token URI: {}", event.token_id, event.address, err);
+            Ok(String::new())
+        },
+        Err(err) => Err(err),
+    }
+}
+
+/// The token URI, `None` if the call is skipped, e.g. it reverted.
+/// The other errors are returned, the range fails rather than losing the event.
+fn skip_reverted(token_uri: Result<String>) -> Result<Option<String>> {
+    match token_uri {
+        Ok(token_uri) => Ok(Some(token_uri)),
+        Err(err) if err.recovery() == Recovery::Skip => {
+            warn!("The event is skipped: {}", err);
+            Ok(None)
+        },
+        Err(err) => Err(err),
     }
+}
+
+/// The name and symbol of a collection are cached until the collection is upgraded.
+/// They are not cached when the node fails, they are asked again for the next event.
+async fn get_erc721_name_and_symbol(evm_client: &EvmClient, address: &H160, retry_policy: &RetryPolicy) -> (String, String) {
+    let cached = evm_client.contract_cache().get(address).unwrap_or_default();
 
-    return None;
+    let name = match cached.name {
+        Some(name) => Some(name),
+        None => {
+            let name = metadata_answer(retry(retry_policy, "get the name", || evm_client.get_erc721_name(address)).await);
+            if let Some(name) = &name {
+                evm_client.contract_cache().update(*address, |info| info.name = Some(name.clone()));
+            }
+            name
+        },
+    };
+    let symbol = match cached.symbol {
+        Some(symbol) => Some(symbol),
+        None => {
+            let symbol = metadata_answer(retry(retry_policy, "get the symbol", || evm_client.get_erc721_symbol(address)).await);
+            if let Some(symbol) = &symbol {
+                evm_client.contract_cache().update(*address, |info| info.symbol = Some(symbol.clone()));
+            }
+            symbol
+        },
+    };
+    (name.unwrap_or_else(|| "Unknown".to_owned()), symbol.unwrap_or_else(|| "Unknown".to_owned()))
 }
 
-async fn process_erc1155_event(evm_client: &EvmClient, event: Erc1155Event, callback: &mut dyn Erc1155EventCallback) {
-    if let Some(token_uri) = get_erc1155_metadata(evm_client, &event).await {
-        // callback
-        callback.on_erc1155_event(event, token_uri).await
+/// The answer of a metadata call which can be cached, `None` if the node failed
+fn metadata_answer(result: Result<String>) -> Option<String> {
+    match result {
+        Ok(value) => Some(value),
+        // the function is not implemented by the contract
+        Err(Error::RevertedCall { .. }) => Some("Unknown".to_owned()),
+        Err(err) => {
+            warn!("The metadata is unknown for now: {}", err);
+            None
+        },
     }
 }
 
+/// `None` if the token URI reverts, the event is skipped
 async fn get_erc1155_metadata(
     evm_client: &EvmClient,
     event: &Erc1155Event,
-) -> Option<String> {
-    if let Ok(token_uri) = evm_client.get_erc1155_token_uri(&event.address, &event.token_id).await {
-        return Some(token_uri);
+    retry_policy: &RetryPolicy,
+) -> Result<Option<String>> {
+    let token_uri = retry(retry_policy, "get the token URI", || {
+        evm_client.get_erc1155_token_uri(&event.address, &event.token_id)
+    });
+    skip_reverted(token_uri.await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rpc_stub, DeadLetterStore, MemoryCheckpointStore, MemoryDeadLetterStore};
+    use std::sync::Mutex;
+    use web3::{transports::Http, types::{H256, U256}, Web3};
+
+    /// A node at block 1000 without any log, the first blocks of the `eth_getLogs` are recorded
+    async fn empty_node(scanned: Arc<Mutex<Vec<u64>>>) -> EvmClient {
+        let url = rpc_stub::serve(move |method, params| match method {
+            "eth_syncing" => Ok(serde_json::json!(false)),
+            "eth_blockNumber" => Ok(serde_json::json!("0x3e8")),
+            "eth_getLogs" => {
+                let from = params[0]["fromBlock"].as_str().unwrap().trim_start_matches("0x");
+                scanned.lock().unwrap().push(u64::from_str_radix(from, 16).unwrap());
+                Ok(serde_json::json!([]))
+            },
+            _ => Err(jsonrpc_core::Error::method_not_found()),
+        })
+        .await;
+        EvmClient::new("Test".to_owned(), Web3::new(Http::new(&url).unwrap()))
+    }
+
+    fn historical_cursor(name: &str, from: u64, end: u64) -> Cursor {
+        Cursor {
+            name: name.to_owned(),
+            from,
+            end: Some(end),
+            origin: EventOrigin::Historical,
+        }
+    }
+
+    /// Never acknowledges a batch
+    struct StuckSink {}
+
+    #[async_trait]
+    impl EventSink for StuckSink {
+        async fn on_batch(&mut self, _batch: &EventBatch) -> Result<()> {
+            futures::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ordered_cursors_fetch_at_the_same_time() {
+        let scanned = Arc::new(Mutex::new(vec![]));
+        let client = empty_node(scanned.clone()).await;
+        let mut tracker_config = TrackerConfig::new(0, 10);
+        tracker_config.prefetch = 1;
+        let cursors = vec![historical_cursor("shard-0", 0, 99), historical_cursor("shard-1", 100, 199)];
+        let checkpoints = MemoryCheckpointStore::default();
+        let mut sink = StuckSink {};
+        let config = EventsConfig::default();
+
+        // the first range of the first cursor is never acknowledged
+        let tracking = run_cursors(&client, &config, &tracker_config, cursors, true, &checkpoints, &mut sink);
+        let second_cursor_done = async {
+            while scanned.lock().unwrap().iter().filter(|from| **from >= 100).count() < 10 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::select! {
+            _ = tracking => panic!("the tracking can not end"),
+            _ = tokio::time::timeout(Duration::from_secs(10), second_cursor_done) => {},
+        }
+        let scanned = scanned.lock().unwrap();
+        assert_eq!(10, scanned.iter().filter(|from| **from >= 100).count(), "the second cursor waited for the first one");
+        assert_eq!(None, checkpoints.load("shard-1").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_spool_is_bounded() {
+        let scanned = Arc::new(Mutex::new(vec![]));
+        let client = empty_node(scanned.clone()).await;
+        let mut tracker_config = TrackerConfig::new(0, 10);
+        tracker_config.prefetch = 1;
+        tracker_config.spool = 2;
+        let cursors = vec![historical_cursor("shard-0", 0, 99), historical_cursor("shard-1", 100, 199)];
+        let checkpoints = MemoryCheckpointStore::default();
+        let mut sink = StuckSink {};
+        let config = EventsConfig::default();
+
+        let second_cursor_scans = || scanned.lock().unwrap().iter().filter(|from| **from >= 100).count();
+        let tracking = run_cursors(&client, &config, &tracker_config, cursors, true, &checkpoints, &mut sink);
+        let second_cursor_waits = async {
+            // the spooled ranges and the range waiting to be spooled
+            while second_cursor_scans() < 4 {
+                sleep(Duration::from_millis(10)).await;
+            }
+            sleep(Duration::from_millis(200)).await;
+        };
+        tokio::select! {
+            _ = tracking => panic!("the tracking can not end"),
+            _ = tokio::time::timeout(Duration::from_secs(10), second_cursor_waits) => {},
+        }
+        assert_eq!(4, second_cursor_scans(), "the spool of the second cursor is not bounded");
+    }
+
+    /// Can not read its checkpoints
+    struct BrokenCheckpoints {}
+
+    impl CheckpointStore for BrokenCheckpoints {
+        fn load(&self, _cursor: &str) -> Result<Option<u64>> {
+            Err(Error::Other("Failed to read the checkpoints".to_owned()))
+        }
+
+        fn save(&self, _cursor: &str, _block_number: u64) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resume_from() {
+        let checkpoints = MemoryCheckpointStore::default();
+        assert_eq!(10, resume_from(&checkpoints, "main", 10).unwrap());
+        checkpoints.save("main", 19).unwrap();
+        // the checkpoint wins over the start block
+        assert_eq!(20, resume_from(&checkpoints, "main", 10).unwrap());
+
+        // the blocks before the checkpoint are not scanned again
+        let scanned = Arc::new(Mutex::new(vec![]));
+        let client = empty_node(scanned.clone()).await;
+        let mut tracker_config = TrackerConfig::new(0, 10);
+        tracker_config.end_block = Some(99);
+        let result = track_events_to_sink(&client, &EventsConfig::default(), &tracker_config, &BrokenCheckpoints {}, &mut StuckSink {}).await;
+        assert!(matches!(result, Err(Error::Other(_))));
+        assert!(scanned.lock().unwrap().is_empty());
+    }
+
+    /// Fails the first `failures` deliveries, records the checkpoint of the cursor at each of them
+    struct FlakySink {
+        failures: u32,
+        checkpoints: MemoryCheckpointStore,
+        seen: Vec<Option<u64>>,
+    }
+
+    #[async_trait]
+    impl EventSink for FlakySink {
+        async fn on_batch(&mut self, batch: &EventBatch) -> Result<()> {
+            self.seen.push(self.checkpoints.load(&batch.cursor).unwrap());
+            if self.seen.len() as u32 <= self.failures {
+                return Err(Error::Other("sink down".to_owned()));
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_after_sink() {
+        let client = empty_node(Arc::new(Mutex::new(vec![]))).await;
+        let mut tracker_config = TrackerConfig::new(0, 10);
+        tracker_config.sink_retry_delay = Duration::from_millis(1);
+        let checkpoints = MemoryCheckpointStore::default();
+        let config = EventsConfig::default();
+
+        // the checkpoint does not move while the sink fails
+        let mut sink = FlakySink { failures: 2, checkpoints: checkpoints.clone(), seen: vec![] };
+        let cursors = vec![historical_cursor("main", 0, 9)];
+        run_cursors(&client, &config, &tracker_config, cursors, true, &checkpoints, &mut sink).await.unwrap();
+        assert_eq!(vec![None, None, None], sink.seen);
+        assert_eq!(Some(9), checkpoints.load("main").unwrap());
+
+        // the tracking halts without a checkpoint once the sink gave up
+        tracker_config.sink_max_attempts = Some(2);
+        let mut sink = FlakySink { failures: 2, checkpoints: checkpoints.clone(), seen: vec![] };
+        let cursors = vec![historical_cursor("halted", 0, 9)];
+        let result = run_cursors(&client, &config, &tracker_config, cursors, true, &checkpoints, &mut sink).await;
+        assert!(result.is_err());
+        assert_eq!(vec![None, None], sink.seen);
+        assert_eq!(None, checkpoints.load("halted").unwrap());
+    }
+
+    /// Records the checkpoint of the `main` cursor when an ERC5192 event is passed
+    struct CheckpointCallback {
+        checkpoints: MemoryCheckpointStore,
+        seen: Vec<Option<u64>>,
+    }
+
+    #[async_trait]
+    impl Erc721EventCallback for CheckpointCallback {
+        async fn on_erc721_event(&mut self, _: Erc721Event, _: String, _: String, _: String, _: EventOrigin) {}
+
+        async fn on_erc5192_event(&mut self, _event: Erc5192Event, _origin: EventOrigin) {
+            self.seen.push(self.checkpoints.load("main").unwrap());
+        }
+    }
+
+    #[async_trait]
+    impl Erc1155EventCallback for CheckpointCallback {
+        async fn on_erc1155_event(&mut self, _: Erc1155Event, _: String, _: EventOrigin) {}
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_after_callbacks() {
+        // a node with a `Locked` event at block 5, every contract supports every interface
+        let url = rpc_stub::serve(|method, _params| match method {
+            "eth_syncing" => Ok(serde_json::json!(false)),
+            "eth_blockNumber" => Ok(serde_json::json!("0x3e8")),
+            "eth_call" => Ok(rpc_stub::encode_bool(true)),
+            "eth_getLogs" => Ok(serde_json::json!([{
+                "address": format!("{:?}", H160::repeat_byte(1)),
+                "topics": ["0x032bc66be43dbccb7487781d168eb7bda224628a3b2c3388bdf69b532a3a1611"],
+                "data": rpc_stub::encode_word(7),
+                "blockHash": format!("{:?}", H256::repeat_byte(3)),
+                "blockNumber": "0x5",
+                "transactionHash": format!("{:?}", H256::repeat_byte(2)),
+                "transactionIndex": "0x0",
+                "logIndex": "0x0",
+                "removed": false,
+            }])),
+            "eth_getBlockByNumber" => Ok(rpc_stub::block(5, 100)),
+            _ => Err(jsonrpc_core::Error::method_not_found()),
+        })
+        .await;
+        let client = EvmClient::new("Test".to_owned(), Web3::new(Http::new(&url).unwrap()));
+        let mut tracker_config = TrackerConfig::new(0, 10);
+        tracker_config.end_block = Some(9);
+        let checkpoints = MemoryCheckpointStore::default();
+        let mut callback = CheckpointCallback { checkpoints: checkpoints.clone(), seen: vec![] };
+        let config = EventsConfig::default();
+        let mut erc1155_cb = CheckpointCallback { checkpoints: checkpoints.clone(), seen: vec![] };
+
+        track_events(&client, &config, &tracker_config, &checkpoints, &mut callback, &mut erc1155_cb).await.unwrap();
+        // the checkpoint of the range is saved once its callbacks have returned
+        assert_eq!(vec![None], callback.seen);
+        assert_eq!(Some(9), checkpoints.load("main").unwrap());
+    }
+
+    /// A node whose calls to the contract of its only log, at block 5, fail with `call_error`
+    async fn poison_node(call_error: jsonrpc_core::Error) -> EvmClient {
+        let url = rpc_stub::serve(move |method, _params| match method {
+            "eth_syncing" => Ok(serde_json::json!(false)),
+            "eth_blockNumber" => Ok(serde_json::json!("0x3e8")),
+            "eth_call" => Err(call_error.clone()),
+            "eth_getLogs" => Ok(serde_json::json!([{
+                "address": format!("{:?}", H160::repeat_byte(1)),
+                "topics": [
+                    "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef",
+                    format!("{:?}", H256::zero()),
+                    format!("{:?}", H256::from(H160::repeat_byte(2))),
+                    format!("{:?}", H256::from_low_u64_be(7)),
+                ],
+                "data": "0x",
+                "blockHash": format!("{:?}", H256::repeat_byte(3)),
+                "blockNumber": "0x5",
+                "transactionHash": format!("{:?}", H256::repeat_byte(2)),
+                "transactionIndex": "0x0",
+                "logIndex": "0x0",
+                "removed": false,
+            }])),
+            _ => Err(jsonrpc_core::Error::method_not_found()),
+        })
+        .await;
+        EvmClient::new("Test".to_owned(), Web3::new(Http::new(&url).unwrap()))
+    }
+
+    fn quarantine_config(dead_letters: &MemoryDeadLetterStore) -> TrackerConfig {
+        let mut tracker_config = TrackerConfig::new(0, 10);
+        tracker_config.dead_letters = Arc::new(dead_letters.clone());
+        // the retry policy gives up on the attempt which quarantines the range
+        tracker_config.quarantine_after = Some(2);
+        tracker_config.retry_policy = RetryPolicy {
+            max_attempts: Some(2),
+            ..RetryPolicy::new(Duration::from_millis(1), Duration::from_millis(1))
+        };
+        tracker_config
+    }
+
+    #[tokio::test]
+    async fn test_quarantine_before_giving_up() {
+        let client = poison_node(jsonrpc_core::Error {
+            code: jsonrpc_core::ErrorCode::ServerError(3),
+            message: "out of gas".to_owned(),
+            data: None,
+        })
+        .await;
+        let dead_letters = MemoryDeadLetterStore::default();
+        let tracker_config = quarantine_config(&dead_letters);
+        let checkpoints = MemoryCheckpointStore::default();
+        let mut sink = FlakySink { failures: 0, checkpoints: checkpoints.clone(), seen: vec![] };
+        let config = EventsConfig::default();
+
+        let cursors = vec![historical_cursor("main", 0, 9)];
+        run_cursors(&client, &config, &tracker_config, cursors, true, &checkpoints, &mut sink).await.unwrap();
+        let letters = dead_letters.list().unwrap();
+        assert_eq!(1, letters.len());
+        assert_eq!(Some(5), letters[0].log.block_number.map(|b| b.as_u64()));
+        assert_eq!(Some(9), checkpoints.load("main").unwrap());
     }
 
-    return None;
+    #[tokio::test]
+    async fn test_node_errors_are_not_quarantined() {
+        let client = poison_node(jsonrpc_core::Error {
+            code: jsonrpc_core::ErrorCode::ServerError(-32000),
+            message: "header not found".to_owned(),
+            data: None,
+        })
+        .await;
+        let dead_letters = MemoryDeadLetterStore::default();
+        let tracker_config = quarantine_config(&dead_letters);
+        let checkpoints = MemoryCheckpointStore::default();
+        let mut sink = FlakySink { failures: 0, checkpoints: checkpoints.clone(), seen: vec![] };
+        let config = EventsConfig::default();
+
+        let cursors = vec![historical_cursor("main", 0, 9)];
+        let result = run_cursors(&client, &config, &tracker_config, cursors, true, &checkpoints, &mut sink).await;
+        assert!(matches!(result, Err(Error::RpcError { .. })));
+        assert!(dead_letters.list().unwrap().is_empty());
+        assert_eq!(None, checkpoints.load("main").unwrap());
+    }
+
+    #[test]
+    fn test_skip_reverted() {
+        assert_eq!(Some("ipfs://7".to_owned()), skip_reverted(Ok("ipfs://7".to_owned())).unwrap());
+        let reverted = Error::RevertedCall {
+            address: H160::repeat_byte(1),
+            method: "tokenURI".to_owned(),
+            message: "execution reverted".to_owned(),
+        };
+        assert_eq!(None, skip_reverted(Err(reverted)).unwrap());
+        // the event is not lost when the node fails, the range fails
+        let unreachable = skip_reverted(Err(Error::rpc(web3::Error::Unreachable, "tokenURI")));
+        assert!(matches!(unreachable, Err(Error::TransientRpcError { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_burned_token_uri() {
+        // the token exists until the block 99, it is burned at the block 100
+        let url = rpc_stub::serve(|method, params| match (method, params[0]["data"].as_str(), params[1].as_str()) {
+            ("eth_call", Some(data), Some(block)) if data.starts_with("0xc87b56dd") => match block {
+                "0x63" => Ok(rpc_stub::encode_string("ipfs://7")),
+                _ => Err(jsonrpc_core::Error {
+                    code: jsonrpc_core::ErrorCode::ServerError(3),
+                    message: "execution reverted".to_owned(),
+                    data: None,
+                }),
+            },
+            ("eth_call", ..) => Ok(rpc_stub::encode_string("Burned")),
+            _ => Err(jsonrpc_core::Error::method_not_found()),
+        })
+        .await;
+        let client = EvmClient::new("Test".to_owned(), Web3::new(Http::new(&url).unwrap()));
+        let burn = |block_number: u64| Erc721Event {
+            block_number: Some(block_number),
+            block_hash: None,
+            block_timestamp: None,
+            address: H160::from_low_u64_be(1),
+            transaction_hash: None,
+            transaction_index: None,
+            log_index: Some(0),
+            removed: false,
+            from: H160::from_low_u64_be(10),
+            to: H160::zero(),
+            token_id: U256::from(7),
+            kind: TransferKind::Burn,
+            soulbound: false,
+        };
+        let retry_policy = RetryPolicy::default();
+
+        let metadata = get_erc721_metadata(&client, &burn(100), &retry_policy).await.unwrap();
+        assert_eq!(Some(("Burned".to_owned(), "Burned".to_owned(), "ipfs://7".to_owned())), metadata);
+        // the token URI is unknown before the burn, the burn is still delivered
+        let metadata = get_erc721_metadata(&client, &burn(50), &retry_policy).await.unwrap();
+        assert_eq!(Some(("Burned".to_owned(), "Burned".to_owned(), String::new())), metadata);
+    }
+
+    #[test]
+    fn test_metadata_answer() {
+        assert_eq!(Some("Hello".to_owned()), metadata_answer(Ok("Hello".to_owned())));
+        let reverted = Error::RevertedCall {
+            address: H160::repeat_byte(1),
+            method: "name".to_owned(),
+            message: "execution reverted".to_owned(),
+        };
+        assert_eq!(Some("Unknown".to_owned()), metadata_answer(Err(reverted)));
+        // a failure of the node is not cached
+        assert_eq!(None, metadata_answer(Err(Error::rpc(web3::Error::Unreachable, "name"))));
+    }
 }
@@ -0,0 +1,360 @@
+//! This module contains the transport of the `EvmClient` over several RPC endpoints.
+//! The requests are sent to the current endpoint. When an endpoint cannot be reached or does not
+//! answer within the timeout of the method, the request is sent to the next one, which becomes the
+//! current endpoint. The errors returned by the node, like reverted calls, are returned as they are.
+//! The requests of each endpoint are throttled by its `RateLimiter`, see `rate_limit`.
+//! The endpoints built from urls are sent the requests by an `HttpEndpoint`, which reads the
+//! `Retry-After` header of the responses refused over the rate limit.
+use crate::{
+    error,
+    http::HttpEndpoint,
+    rate_limit::{RateLimit, RateLimiter},
+    retry::millis,
+    Error, Result,
+};
+use futures::future::{BoxFuture, FutureExt};
+use jsonrpc_core::{Call, Value};
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use web3::{transports::Http, BatchTransport, RequestId, Transport};
+
+/// The default timeout of `eth_getLogs`
+pub const DEFAULT_LOGS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The default timeout of the requests for the head and the blocks
+pub const DEFAULT_HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The default timeout of `eth_call` and the other requests
+pub const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// The time given to an endpoint to answer, by method.
+/// In `config.toml`, the timeouts are in milliseconds and every field is optional:
+///
+/// ```toml
+/// [timeouts]
+/// logs_ms = 60000
+/// head_ms = 10000
+/// call_ms = 20000
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RpcTimeouts {
+    /// The timeout of `eth_getLogs`
+    #[serde(rename = "logs_ms", with = "millis")]
+    pub logs: Duration,
+    /// The timeout of `eth_blockNumber`, `eth_syncing` and `eth_getBlockByNumber`
+    #[serde(rename = "head_ms", with = "millis")]
+    pub head: Duration,
+    /// The timeout of `eth_call` and of the other methods
+    #[serde(rename = "call_ms", with = "millis")]
+    pub call: Duration,
+}
+
+impl RpcTimeouts {
+    /// The timeout of a method
+    pub fn of(&self, method: &str) -> Duration {
+        match method {
+            "eth_getLogs" => self.logs,
+            "eth_blockNumber" | "eth_syncing" | "eth_getBlockByNumber" => self.head,
+            _ => self.call,
+        }
+    }
+}
+
+impl Default for RpcTimeouts {
+    fn default() -> Self {
+        RpcTimeouts {
+            logs: DEFAULT_LOGS_TIMEOUT,
+            head: DEFAULT_HEAD_TIMEOUT,
+            call: DEFAULT_CALL_TIMEOUT,
+        }
+    }
+}
+
+/// An RPC endpoint of the transport
+#[derive(Debug, Clone)]
+enum Endpoint {
+    /// The HTTP transport of web3, the status and the headers of its responses are not exposed
+    Web3(Http),
+    /// The HTTP transport of this crate
+    Http(HttpEndpoint),
+}
+
+impl Endpoint {
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        match self {
+            Endpoint::Web3(http) => http.prepare(method, params),
+            Endpoint::Http(http) => http.prepare(method, params),
+        }
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> BoxFuture<'static, web3::Result<Value>> {
+        match self {
+            Endpoint::Web3(http) => http.send(id, request).boxed(),
+            Endpoint::Http(http) => http.send(id, request),
+        }
+    }
+
+    fn send_batch(&self, requests: Vec<(RequestId, Call)>) -> BoxFuture<'static, web3::Result<Vec<web3::Result<Value>>>> {
+        match self {
+            Endpoint::Web3(http) => http.send_batch(requests).boxed(),
+            Endpoint::Http(http) => http.send_batch(requests),
+        }
+    }
+}
+
+/// An HTTP transport over several endpoints, shared by all its clones
+#[derive(Debug, Clone)]
+pub struct FailoverTransport {
+    endpoints: Arc<Vec<Endpoint>>,
+    current: Arc<AtomicUsize>,
+    timeouts: RpcTimeouts,
+    limiters: Arc<Vec<RateLimiter>>,
+}
+
+impl FailoverTransport {
+    /// A transport over the web3 endpoints, in order of preference.
+    /// The delay asked by the `Retry-After` header of a response refused over the rate limit is not
+    /// known, the endpoint is paused for the `cooldown` of its `RateLimit`. See `from_urls`.
+    pub fn new(endpoints: Vec<Http>) -> Result<FailoverTransport> {
+        FailoverTransport::with_endpoints(endpoints.into_iter().map(Endpoint::Web3).collect())
+    }
+
+    fn with_endpoints(endpoints: Vec<Endpoint>) -> Result<FailoverTransport> {
+        if endpoints.is_empty() {
+            return Err(Error::ConfigError("At least one RPC endpoint is required".to_owned()));
+        }
+        Ok(FailoverTransport {
+            limiters: Arc::new(endpoints.iter().map(|_| RateLimiter::default()).collect()),
+            endpoints: Arc::new(endpoints),
+            current: Arc::new(AtomicUsize::new(0)),
+            timeouts: RpcTimeouts::default(),
+        })
+    }
+
+    /// The same transport, with these timeouts
+    pub fn with_timeouts(self, timeouts: RpcTimeouts) -> FailoverTransport {
+        FailoverTransport { timeouts, ..self }
+    }
+
+    /// The same transport, the requests of the endpoint `i` are limited by `rate_limits[i]`.
+    /// The endpoints without a rate limit are not limited.
+    pub fn with_rate_limits(self, rate_limits: &[RateLimit]) -> FailoverTransport {
+        let limiters = (0..self.endpoints.len())
+            .map(|i| rate_limits.get(i).cloned().map(RateLimiter::new).unwrap_or_default())
+            .collect();
+        FailoverTransport {
+            limiters: Arc::new(limiters),
+            ..self
+        }
+    }
+
+    /// A transport over the endpoints of `urls`, in order of preference
+    pub fn from_urls<S: AsRef<str>>(urls: &[S]) -> Result<FailoverTransport> {
+        let endpoints = urls
+            .iter()
+            .map(|url| HttpEndpoint::new(url.as_ref()).map(Endpoint::Http))
+            .collect::<std::result::Result<Vec<Endpoint>, web3::Error>>()?;
+        FailoverTransport::with_endpoints(endpoints)
+    }
+
+    /// The index of the endpoint the requests are sent to
+    pub fn current(&self) -> usize {
+        self.current.load(Ordering::Relaxed)
+    }
+
+    /// The rate limiter of the endpoint `index`
+    pub fn limiter(&self, index: usize) -> Option<&RateLimiter> {
+        self.limiters.get(index)
+    }
+
+    /// Send `cost` requests to the current endpoint, then to the next ones while they cannot be reached
+    /// or do not answer within `timeout`. An endpoint which refuses them over its rate limit is paused.
+    fn send_with_failover<T, F>(&self, cost: usize, timeout: Duration, send: F) -> BoxFuture<'static, web3::Result<T>>
+    where
+        T: Send + 'static,
+        F: Fn(&Endpoint) -> BoxFuture<'static, web3::Result<T>> + Send + 'static,
+    {
+        let endpoints = self.endpoints.clone();
+        let limiters = self.limiters.clone();
+        let current = self.current.clone();
+        async move {
+            let start = current.load(Ordering::Relaxed);
+            let mut last_err = web3::Error::Unreachable;
+            for i in 0..endpoints.len() {
+                let index = (start + i) % endpoints.len();
+                let permit = limiters[index].acquire(cost).await;
+                let result = tokio::time::timeout(timeout, send(&endpoints[index]))
+                    .await
+                    .unwrap_or_else(|_| Err(timed_out(timeout)));
+                drop(permit);
+                if let Err(err) = &result {
+                    if let Some(retry_after) = error::rate_limit(err) {
+                        warn!("The RPC endpoint {} is rate limited: {}", index, err);
+                        limiters[index].pause(retry_after);
+                    }
+                }
+                match result {
+                    Err(err) if is_unreachable(&err) => {
+                        warn!("The RPC endpoint {} is unreachable: {}", index, err);
+                        current.store((index + 1) % endpoints.len(), Ordering::Relaxed);
+                        last_err = err;
+                    },
+                    result => return result,
+                }
+            }
+            Err(last_err)
+        }
+        .boxed()
+    }
+}
+
+/// The error of a request without answer, classified as `Error::Timeout`
+fn timed_out(timeout: Duration) -> web3::Error {
+    web3::Error::Io(io::Error::new(io::ErrorKind::TimedOut, format!("no answer within {:?}", timeout)))
+}
+
+/// The errors of the endpoint itself, the request may succeed on another endpoint
+fn is_unreachable(err: &web3::Error) -> bool {
+    matches!(err, web3::Error::Unreachable | web3::Error::Transport(_) | web3::Error::Io(_))
+}
+
+impl Transport for FailoverTransport {
+    type Out = BoxFuture<'static, web3::Result<Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<Value>) -> (RequestId, Call) {
+        self.endpoints[self.current()].prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: Call) -> Self::Out {
+        let timeout = self.timeouts.of(method(&request));
+        self.send_with_failover(1, timeout, move |endpoint| endpoint.send(id, request.clone()))
+    }
+}
+
+impl BatchTransport for FailoverTransport {
+    type Batch = BoxFuture<'static, web3::Result<Vec<web3::Result<Value>>>>;
+
+    fn send_batch<T>(&self, requests: T) -> Self::Batch
+    where
+        T: IntoIterator<Item = (RequestId, Call)>,
+    {
+        let requests: Vec<(RequestId, Call)> = requests.into_iter().collect();
+        // a batch is given the longest timeout of its methods
+        let timeout = requests
+            .iter()
+            .map(|(_, request)| self.timeouts.of(method(request)))
+            .max()
+            .unwrap_or(self.timeouts.call);
+        // a batch takes a token per request
+        let cost = requests.len();
+        self.send_with_failover(cost, timeout, move |endpoint| endpoint.send_batch(requests.clone()))
+    }
+}
+
+fn method(request: &Call) -> &str {
+    match request {
+        Call::MethodCall(call) => &call.method,
+        Call::Notification(notification) => &notification.method,
+        Call::Invalid { .. } => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failover_transport() {
+        assert!(FailoverTransport::new(vec![]).is_err());
+        assert!(FailoverTransport::from_urls(&["not a url"]).is_err());
+
+        let transport = FailoverTransport::from_urls(&["http://localhost:8545", "http://localhost:8546"]).unwrap();
+        assert_eq!(0, transport.current());
+    }
+
+    #[tokio::test]
+    async fn test_failover_to_next_endpoint() {
+        // nothing listens on these ports
+        let transport = FailoverTransport::from_urls(&["http://127.0.0.1:1", "http://127.0.0.1:2"]).unwrap();
+        let result = transport.execute("eth_blockNumber", vec![]).await;
+        assert!(result.is_err());
+        // both were tried, the first one is the current endpoint again
+        assert_eq!(0, transport.current());
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        // accepts the connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut connections = vec![];
+            while let Ok((connection, _)) = listener.accept().await {
+                connections.push(connection);
+            }
+        });
+
+        let timeouts = RpcTimeouts {
+            head: Duration::from_millis(50),
+            ..RpcTimeouts::default()
+        };
+        assert_eq!(Duration::from_millis(50), timeouts.of("eth_blockNumber"));
+        assert_eq!(DEFAULT_LOGS_TIMEOUT, timeouts.of("eth_getLogs"));
+        assert_eq!(DEFAULT_CALL_TIMEOUT, timeouts.of("eth_getCode"));
+
+        let transport = FailoverTransport::from_urls(&[url]).unwrap().with_timeouts(timeouts);
+        let err = transport.execute("eth_blockNumber", vec![]).await.unwrap_err();
+        assert!(matches!(Error::rpc(err, "eth_blockNumber"), Error::Timeout { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_endpoint_is_paused() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // refuses every request over its rate limit, asks for a delay the first time
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut retry_after = "Retry-After: 1\r\n";
+            while let Ok((mut connection, _)) = listener.accept().await {
+                let mut request = [0; 4096];
+                let _ = connection.read(&mut request).await;
+                let response = format!(
+                    "HTTP/1.1 429 Too Many Requests\r\n{}Content-Length: 0\r\nConnection: close\r\n\r\n",
+                    retry_after
+                );
+                let _ = connection.write_all(response.as_bytes()).await;
+                retry_after = "";
+            }
+        });
+
+        let rate_limit = RateLimit {
+            cooldown: Duration::from_millis(200),
+            ..RateLimit::new(100.0)
+        };
+        let transport = FailoverTransport::from_urls(&[url]).unwrap().with_rate_limits(std::slice::from_ref(&rate_limit));
+        assert_eq!(Some(&rate_limit), transport.limiter(0).map(RateLimiter::limit));
+
+        let err = transport.execute("eth_blockNumber", vec![]).await.unwrap_err();
+        let err = Error::rpc(err, "eth_blockNumber");
+        assert!(matches!(err, Error::RateLimited { retry_after: Some(delay), .. } if delay == Duration::from_secs(1)));
+        // the next request waits for the delay asked by the endpoint
+        let start = std::time::Instant::now();
+        let err = transport.execute("eth_blockNumber", vec![]).await.unwrap_err();
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert!(matches!(Error::rpc(err, "eth_blockNumber"), Error::RateLimited { retry_after: None, .. }));
+        // then for the cooldown, without a delay
+        let start = std::time::Instant::now();
+        assert!(transport.execute("eth_blockNumber", vec![]).await.is_err());
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(200) && elapsed < Duration::from_secs(1));
+    }
+}
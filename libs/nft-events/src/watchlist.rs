@@ -0,0 +1,80 @@
+//! This module contains the watchlist of the wallet-centric tracking.
+//! Instead of the whole chain, only the NFTs moving in and out of the watched addresses are
+//! tracked. The addresses are sent in the topic filters of `eth_getLogs`: `_from` and `_to` are the
+//! topics 1 and 2 of an ERC721 `Transfer`, but the topics 2 and 3 of the ERC1155 transfers.
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, RwLock},
+};
+use web3::types::H160;
+
+/// The watched addresses, shared by all the clones of a watchlist.
+/// The watchlist can be updated while tracking, the changes apply from the next range of blocks.
+/// The past transfers of a newly watched address are not fetched.
+#[derive(Debug, Clone, Default)]
+pub struct Watchlist {
+    addresses: Arc<RwLock<BTreeSet<H160>>>,
+}
+
+impl Watchlist {
+    /// Create a watchlist of `addresses`
+    pub fn new<I: IntoIterator<Item = H160>>(addresses: I) -> Watchlist {
+        Watchlist {
+            addresses: Arc::new(RwLock::new(addresses.into_iter().collect())),
+        }
+    }
+
+    /// Watch an address.
+    /// Returns `true` if the address was not watched yet.
+    pub fn add(&self, address: H160) -> bool {
+        self.addresses.write().unwrap().insert(address)
+    }
+
+    /// Stop watching an address.
+    /// Returns `true` if the address was watched.
+    pub fn remove(&self, address: &H160) -> bool {
+        self.addresses.write().unwrap().remove(address)
+    }
+
+    /// Returns `true` if the address is watched
+    pub fn contains(&self, address: &H160) -> bool {
+        self.addresses.read().unwrap().contains(address)
+    }
+
+    /// The watched addresses, in ascending order
+    pub fn addresses(&self) -> Vec<H160> {
+        self.addresses.read().unwrap().iter().cloned().collect()
+    }
+
+    /// The number of watched addresses
+    pub fn len(&self) -> usize {
+        self.addresses.read().unwrap().len()
+    }
+
+    /// Returns `true` if no address is watched
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_watchlist() {
+        let alice = H160::from_low_u64_be(10);
+        let bob = H160::from_low_u64_be(11);
+        let watchlist = Watchlist::new(vec![bob]);
+
+        // clones share the same addresses
+        let clone = watchlist.clone();
+        assert_eq!(true, clone.add(alice));
+        assert_eq!(false, clone.add(alice));
+        assert_eq!(vec![alice, bob], watchlist.addresses());
+
+        assert_eq!(true, watchlist.remove(&bob));
+        assert_eq!(false, clone.contains(&bob));
+        assert_eq!(1, clone.len());
+    }
+}
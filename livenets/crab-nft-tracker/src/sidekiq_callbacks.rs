@@ -1,4 +1,4 @@
-use nft_events::{Erc1155Event, Erc1155EventCallback, Erc721Event, Erc721EventCallback};
+use nft_events::{Erc1155Event, Erc1155EventCallback, Erc721Event, Erc721EventCallback, EventOrigin};
 use crate::sidekiq_helper;
 
 pub struct EthereumErc721EventCallback {}
@@ -11,6 +11,7 @@ impl Erc721EventCallback for EthereumErc721EventCallback {
         name: String,
         symbol: String,
         token_uri: String,
+        origin: EventOrigin,
     ) {
         sidekiq_helper::send_erc721(
             "Crab".to_string(),
@@ -18,6 +19,7 @@ impl Erc721EventCallback for EthereumErc721EventCallback {
             name,
             symbol,
             token_uri,
+            origin,
         );
     }
 }
@@ -30,11 +32,13 @@ impl Erc1155EventCallback for EthereumErc1155EventCallback {
         &mut self,
         event: Erc1155Event,
         token_uri: String,
+        origin: EventOrigin,
     ) {
         sidekiq_helper::send_erc1155(
             "Crab".to_string(),
             event,
             token_uri,
+            origin,
         );
     }
 }
@@ -1,4 +1,4 @@
-use nft_events::{Erc1155Event, Erc721Event};
+use nft_events::{Erc1155Event, Erc721Event, Event, EventOrigin, VersionedEvent};
 use sidekiq::{create_redis_pool, Client, ClientOpts, Job, JobOpts};
 use serde_json;
 
@@ -10,58 +10,30 @@ pub fn send_erc721(
     name: String,
     symbol: String,
     token_uri: String,
+    origin: EventOrigin,
 ) {
     if event.block_number.is_some() && event.transaction_hash.is_some() {
-        let block_number = event.block_number.unwrap();
-        let address = format!("{:?}", event.address);
-        let transaction_hash = format!("{:?}", event.transaction_hash.unwrap());
-        let from = format!("{:?}", event.from);
-        let to = format!("{:?}", event.to);
-        let token_id = event.token_id.to_string();
-
-        let job = build_erc721_job(
-            blockchain, 
-            block_number, 
-            address, 
-            transaction_hash, 
-            from, 
-            to, 
-            token_id, 
-            token_uri, 
-            name, 
-            symbol, 
-        );
-
+        let job = build_erc721_job(blockchain, event, token_uri, name, symbol, origin);
         push(job);
     }
 }
 
 fn build_erc721_job(
     blockchain: String,
-    block_number: u64,
-    address: String,
-    transaction_hash: String,
-    from: String,
-    to: String,
-    token_id: String,
+    event: Erc721Event,
     token_uri: String,
     name: String,
     symbol: String,
+    origin: EventOrigin,
 ) -> Job {
     let class = "ProcessErc721EventWorker".to_string();
 
-    let value = serde_json::json!({
-        "blockchain": blockchain,
-        "block_number": block_number,
-        "address": address,
-        "transaction_hash": transaction_hash,
-        "from": from,
-        "to": to,
-        "token_id": token_id,
-        "token_uri": token_uri,
-        "name": name,
-        "symbol": symbol,
-    });
+    let mut value = event_value(Event::Erc721(event));
+    value["blockchain"] = Value::from(blockchain);
+    value["token_uri"] = Value::from(token_uri);
+    value["name"] = Value::from(name);
+    value["symbol"] = Value::from(symbol);
+    value["origin"] = Value::from(origin.as_str());
     let args: Vec<Value> = vec![value];
 
     let job_opts = JobOpts {
@@ -75,56 +47,26 @@ pub fn send_erc1155(
     blockchain: String,
     event: Erc1155Event,
     token_uri: String,
+    origin: EventOrigin,
 ) {
     if event.block_number.is_some() && event.transaction_hash.is_some() {
-        let block_number = event.block_number.unwrap();
-        let address = format!("{:?}", event.address);
-        let transaction_hash = format!("{:?}", event.transaction_hash.unwrap());
-        let from = format!("{:?}", event.from);
-        let to = format!("{:?}", event.to);
-        let token_id = event.token_id.to_string();
-        let amount = event.amount.as_u128();
-
-        let job = build_erc1155_job(
-            blockchain, 
-            block_number, 
-            address, 
-            transaction_hash, 
-            from, 
-            to, 
-            token_id, 
-            amount, 
-            token_uri, 
-        );
-
+        let job = build_erc1155_job(blockchain, event, token_uri, origin);
         push(job);
     }
 }
 
 fn build_erc1155_job(
     blockchain: String,
-    block_number: u64,
-    address: String,
-    transaction_hash: String,
-    from: String,
-    to: String,
-    token_id: String,
-    amount: u128,
+    event: Erc1155Event,
     token_uri: String,
+    origin: EventOrigin,
 ) -> Job {
     let class = "ProcessErc1155EventWorker".to_string();
 
-    let value = serde_json::json!({
-        "blockchain": blockchain,
-        "block_number": block_number,
-        "address": address,
-        "transaction_hash": transaction_hash,
-        "from": from,
-        "to": to,
-        "token_id": token_id,
-        "token_uri": token_uri,
-        "amount": amount as u64,
-    });
+    let mut value = event_value(Event::Erc1155(event));
+    value["blockchain"] = Value::from(blockchain);
+    value["token_uri"] = Value::from(token_uri);
+    value["origin"] = Value::from(origin.as_str());
     let args: Vec<Value> = vec![value];
 
     let job_opts = JobOpts {
@@ -134,6 +76,12 @@ fn build_erc1155_job(
     Job::new(class, args, job_opts)
 }
 
+// The event serialized with the versioned schema of nft-events,
+// the addresses are checksummed and the token_id and amount are decimal strings.
+fn event_value(event: Event) -> Value {
+    serde_json::to_value(VersionedEvent::from(event)).unwrap()
+}
+
 fn get_client() -> Client {
     let client_opts = ClientOpts {
         namespace: None,
@@ -152,20 +100,42 @@ fn push(job: Job) {
     }
 }
 
+#[cfg(test)]
+fn h160(s: &str) -> web3::types::H160 {
+    use std::str::FromStr;
+    web3::types::H160::from_str(s).unwrap()
+}
+
+#[cfg(test)]
+fn h256(s: &str) -> web3::types::H256 {
+    use std::str::FromStr;
+    web3::types::H256::from_str(s).unwrap()
+}
+
 #[test]
 fn test_build_erc721_job() {
+    let event = Erc721Event {
+        block_number: Some(123456),
+        block_hash: None,
+        block_timestamp: Some(1629525603),
+        address: h160("0xca0d36c67a0c1bf6b28e76fb8c2188c31b87d152"),
+        transaction_hash: Some(h256("0x42ac0589bf82ccff3358859728890c520f616ac5e184cc685113732880df0825")),
+        transaction_index: Some(1),
+        log_index: Some(2),
+        removed: false,
+        from: h160("0x8628ff3ac814ee8937c10860b85d55e6aa67cfa2"),
+        to: h160("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"),
+        token_id: 1111.into(),
+        kind: nft_events::TransferKind::Transfer,
+        soulbound: false,
+    };
     let job = build_erc721_job(
         "Ethereum".to_string(),
-        123456,
-        "0xca0d36c67a0c1bf6b28e76fb8c2188c31b87d152".to_string(),
-        "0x42ac0589bf82ccff3358859728890c520f616ac5e184cc685113732880df0825".to_string(),
-        "0x8628ff3ac814ee8937c10860b85d55e6aa67cfa2".to_string(),
-        "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".to_string(),
-        "1111".to_string(),
+        event,
         "https://token_uri".to_string(),
         "Hello".to_string(),
         "HL".to_string(),
-        Some(1234),
+        EventOrigin::Live,
     );
 
     push(job)
@@ -173,16 +143,28 @@ fn test_build_erc721_job() {
 
 #[test]
 fn test_build_erc1155_job() {
+    let event = Erc1155Event {
+        block_number: Some(123456),
+        block_hash: None,
+        block_timestamp: Some(1629525603),
+        address: h160("0xca0d36c67a0c1bf6b28e76fb8c2188c31b87d152"),
+        transaction_hash: Some(h256("0x42ac0589bf82ccff3358859728890c520f616ac5e184cc685113732880df0825")),
+        transaction_index: Some(1),
+        log_index: Some(2),
+        removed: false,
+        operator: h160("0x8628ff3ac814ee8937c10860b85d55e6aa67cfa2"),
+        from: h160("0x8628ff3ac814ee8937c10860b85d55e6aa67cfa2"),
+        to: h160("0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"),
+        token_id: 1111.into(),
+        amount: 1234.into(),
+        kind: nft_events::TransferKind::Transfer,
+        batch_index: 0,
+    };
     let job = build_erc1155_job(
         "Ethereum".to_string(),
-        123456,
-        "0xca0d36c67a0c1bf6b28e76fb8c2188c31b87d152".to_string(),
-        "0x42ac0589bf82ccff3358859728890c520f616ac5e184cc685113732880df0825".to_string(),
-        "0x8628ff3ac814ee8937c10860b85d55e6aa67cfa2".to_string(),
-        "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".to_string(),
-        "1111".to_string(),
-        1234,
+        event,
         "https://token_uri".to_string(),
+        EventOrigin::Historical,
     );
 
     push(job)
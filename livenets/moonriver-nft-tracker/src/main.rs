@@ -1,55 +1,42 @@
 use directories_next::ProjectDirs;
-use nft_events::{Erc1155Event, Erc1155EventCallback, Erc721Event, Erc721EventCallback};
+use nft_events::{println_callbacks, shutdown, tracker, BackfillConfig, EventsConfig, FileCheckpointStore, FileDeadLetterStore, RateLimit, RetryPolicy, RpcTimeouts, TrackerConfig};
 use std::env;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::str::FromStr;
+use std::time::Duration;
+use web3::types::H160;
 
 #[macro_use]
 extern crate log;
 
-#[macro_use]
-extern crate async_trait;
-
-struct MoonriverErc721EventCallback {}
-
-#[async_trait]
-impl Erc721EventCallback for MoonriverErc721EventCallback {
-    async fn on_erc721_event(
-        &mut self,
-        event: Erc721Event,
-        name: String,
-        symbol: String,
-        token_uri: String,
-    ) {
-        println!("------------------------------------------------------------------------------------------");
-        println!("event: {:?}", event);
-        println!(
-            "name: {:?}, symbol: {:?}, token_uri: {:?}",
-            name, symbol, token_uri
-        );
-    }
-}
-
-struct MoonriverErc1155EventCallback {}
-
-#[async_trait]
-impl Erc1155EventCallback for MoonriverErc1155EventCallback {
-    async fn on_erc1155_event(
-        &mut self,
-        event: Erc1155Event,
-        token_uri: String,
-    ) {
-        println!("++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++");
-        println!("event: {:?}", event);
-        println!("token_uri: {:?}", token_uri);
-    }
-}
-
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
 struct MoonriverNftTrackerConfig {
     rpc: String,
     step: u64,
+    /// Only the NFTs of these contracts are tracked, all the contracts if it is empty
+    #[serde(default)]
+    allowlist: Vec<String>,
+    /// The NFTs of these contracts are never tracked
+    #[serde(default)]
+    denylist: Vec<String>,
+    /// The seconds given to the current range to be done on SIGINT or SIGTERM, 30 by default
+    #[serde(default)]
+    drain_timeout: Option<u64>,
+    /// The retry policy of the failed requests, see `RetryPolicy`
+    #[serde(default)]
+    retry: RetryPolicy,
+    /// The milliseconds given to the rpc endpoint to answer, by method, see `RpcTimeouts`
+    #[serde(default)]
+    timeouts: RpcTimeouts,
+    /// The seconds given to fetch the events of a range before it is fetched again, 600 by default
+    #[serde(default)]
+    range_deadline: Option<u64>,
+    /// The quota of the rpc endpoint, see `RateLimit`
+    #[serde(default)]
+    rate_limit: RateLimit,
 }
 
 impl Default for MoonriverNftTrackerConfig {
@@ -57,10 +44,39 @@ impl Default for MoonriverNftTrackerConfig {
         MoonriverNftTrackerConfig {
             rpc: "https://rpc.moonriver.moonbeam.network".to_owned(),
             step: 6,
+            allowlist: vec![],
+            denylist: vec![],
+            drain_timeout: None,
+            retry: RetryPolicy::default(),
+            timeouts: RpcTimeouts::default(),
+            range_deadline: None,
+            rate_limit: RateLimit::default(),
         }
     }
 }
 
+impl MoonriverNftTrackerConfig {
+    /// The config of a tracking from `start_from`, the quarantined logs are put in `dead_letters`
+    fn tracker_config(&self, start_from: u64, dead_letters: &Arc<FileDeadLetterStore>) -> TrackerConfig {
+        let mut tracker_config = TrackerConfig::new(start_from, self.step);
+        tracker_config.drain_timeout = self.drain_timeout.map(Duration::from_secs).unwrap_or(shutdown::DEFAULT_DRAIN_TIMEOUT);
+        tracker_config.rpc_timeouts = self.timeouts.clone();
+        tracker_config.rate_limit = self.rate_limit.clone();
+        tracker_config.range_deadline = Some(self.range_deadline.map(Duration::from_secs).unwrap_or(tracker::DEFAULT_RANGE_DEADLINE));
+        tracker_config.retry_policy = self.retry.clone();
+        tracker_config.dead_letters = dead_letters.clone();
+        tracker_config
+    }
+
+    /// The config of a backfill of `[start, end]`, the quarantined logs are put in `dead_letters`
+    fn backfill_config(&self, start: u64, end: u64, dead_letters: &Arc<FileDeadLetterStore>) -> BackfillConfig {
+        BackfillConfig::from_tracker_config(&self.tracker_config(start, dead_letters), end)
+    }
+}
+
+/// The number of tokens fetched at the same time by the snapshot command
+const SNAPSHOT_CONCURRENCY: usize = 8;
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     env::set_var(
@@ -88,18 +104,107 @@ async fn main() -> anyhow::Result<()> {
     info!("  {} rpc : {}", chain_name, rpc);
     info!("  Track step : {} blocks", step);
 
+    let mut events_config = EventsConfig::default();
+    events_config.allowlist = cfg.allowlist.iter().map(|a| H160::from_str(a)).collect::<Result<_, _>>()?;
+    events_config.denylist = cfg.denylist.iter().map(|a| H160::from_str(a)).collect::<Result<_, _>>()?;
+    if !events_config.allowlist.is_empty() {
+        info!("  Allowlist : {} contracts", events_config.allowlist.len());
+    }
+    if !events_config.denylist.is_empty() {
+        info!("  Denylist : {} contracts", events_config.denylist.len());
+    }
+
+    let dead_letters_dir: PathBuf = [data_dir, "dead-letters"].iter().collect();
+    let dead_letters = Arc::new(FileDeadLetterStore::new(dead_letters_dir)?);
+
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
+    if args.len() == 3 && args[1] == "snapshot" {
+        if let Ok(address) = H160::from_str(&args[2]) {
+            let mut erc721_token_cb = println_callbacks::PrintlnErc721TokenCallback {};
+            nft_events::snapshot_collection(
+                chain_name,
+                rpc,
+                address,
+                SNAPSHOT_CONCURRENCY,
+                &cfg.timeouts,
+                &cfg.rate_limit,
+                &mut erc721_token_cb,
+            )
+            .await?;
+        } else {
+            println!("Usage: moonriver-nft-tracker snapshot <CONTRACT_ADDRESS>")
+        }
+    } else if args.len() == 4 && args[1] == "backfill" {
+        if let (Ok(start), Ok(end)) = (args[2].parse::<u64>(), args[3].parse::<u64>()) {
+            let mut erc721_cb = println_callbacks::PrintlnErc721EventCallback {};
+            let mut erc1155_cb = println_callbacks::PrintlnErc1155EventCallback {};
+            let backfill_config = cfg.backfill_config(start, end, &dead_letters);
+            let checkpoints_dir: PathBuf = [data_dir, "checkpoints"].iter().collect();
+            let checkpoints = FileCheckpointStore::new(checkpoints_dir)?;
+            nft_events::start_backfill(
+                chain_name,
+                rpc,
+                &backfill_config,
+                &events_config,
+                &checkpoints,
+                &mut erc721_cb,
+                &mut erc1155_cb,
+            )
+            .await?;
+        } else {
+            println!("Usage: moonriver-nft-tracker backfill <START_BLOCK_NUMBER> <END_BLOCK_NUMBER>")
+        }
+    } else if args.len() == 3 && args[1] == "live-backfill" {
+        if let Ok(start_from) = args[2].parse::<u64>() {
+            let mut erc721_cb = println_callbacks::PrintlnErc721EventCallback {};
+            let mut erc1155_cb = println_callbacks::PrintlnErc1155EventCallback {};
+            let checkpoints_dir: PathBuf = [data_dir, "checkpoints"].iter().collect();
+            let checkpoints = FileCheckpointStore::new(checkpoints_dir)?;
+            let tracker_config = cfg.tracker_config(start_from, &dead_letters);
+            nft_events::start_tracking_with_backfill(
+                chain_name,
+                rpc,
+                &tracker_config,
+                &events_config,
+                &checkpoints,
+                &mut erc721_cb,
+                &mut erc1155_cb,
+            )
+            .await?;
+        } else {
+            println!("Usage: moonriver-nft-tracker live-backfill <START_BLOCK_NUMBER>")
+        }
+    } else if args.len() == 2 && args[1] == "replay" {
+        let mut erc721_cb = println_callbacks::PrintlnErc721EventCallback {};
+        let mut erc1155_cb = println_callbacks::PrintlnErc1155EventCallback {};
+        let replayed = nft_events::replay_dead_letters(
+            chain_name,
+            rpc,
+            &events_config,
+            &cfg.retry,
+            &cfg.timeouts,
+            &cfg.rate_limit,
+            dead_letters.as_ref(),
+            &mut erc721_cb,
+            &mut erc1155_cb,
+        )
+        .await?;
+        info!("{} quarantined logs replayed", replayed);
+    } else if args.len() != 2 {
         println!("Usage: moonriver-nft-tracker <ETHEREUM_BLOCK_NUMBER>")
     } else {
         if let Ok(start_from) = args[1].parse::<u64>() {
-            let mut erc721_cb = MoonriverErc721EventCallback {};
-            let mut erc1155_cb = MoonriverErc1155EventCallback {};
-            nft_events::start_tracking(
+            let mut erc721_cb = println_callbacks::PrintlnErc721EventCallback {};
+            let mut erc1155_cb = println_callbacks::PrintlnErc1155EventCallback {};
+            let checkpoints_dir: PathBuf = [data_dir, "checkpoints"].iter().collect();
+            let checkpoints = FileCheckpointStore::new(checkpoints_dir)?;
+            let tracker_config = cfg.tracker_config(start_from, &dead_letters);
+            nft_events::start_tracking_with_config(
                 chain_name,
                 rpc,
-                start_from,
-                step,
+                &tracker_config,
+                &events_config,
+                &checkpoints,
                 &mut erc721_cb,
                 &mut erc1155_cb,
             )